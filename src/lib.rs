@@ -6,37 +6,251 @@
 // You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 #![deny(warnings)]
+#![cfg_attr(not(feature = "std"), no_std)]
 
+#[cfg(not(any(feature = "std", feature = "alloc")))]
+compile_error!("key-set requires either the `std` or the `alloc` feature");
+
+extern crate alloc;
+
+use alloc::collections::{btree_map, BTreeMap};
+use alloc::sync::Arc;
+use alloc::{vec, vec::Vec};
 use ark_serialize::*;
+#[cfg(feature = "serde")]
 use commit::{Commitment, Committable};
-use core::fmt::Debug;
+use core::cmp::Ordering;
+use core::convert::TryFrom;
+use core::fmt::{self, Debug, Display, Formatter};
+use core::hash::{Hash, Hasher};
+use core::iter::FromIterator;
+#[cfg(any(feature = "std", feature = "serde"))]
+use core::marker::PhantomData;
+use core::ops::Bound::*;
+#[cfg(feature = "compression")]
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+#[cfg(feature = "std")]
 use jf_cap::{
-    proof::{freeze::FreezeProvingKey, mint::MintProvingKey, transfer::TransferProvingKey},
+    proof::{
+        freeze::{FreezeProvingKey, FreezeVerifyingKey},
+        mint::{MintProvingKey, MintVerifyingKey},
+        transfer::{TransferProvingKey, TransferVerifyingKey},
+    },
     TransactionVerifyingKey,
 };
-use serde::{Deserialize, Serialize};
-use serde_with::serde_as;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "serde")]
+use serde_with::{serde_as, DeserializeAs, Same, SerializeAs};
+#[cfg(feature = "checksum")]
+use sha2::{Digest, Sha256};
 use snafu::Snafu;
-use std::collections::BTreeMap;
-use std::iter::FromIterator;
-use std::ops::Bound::*;
+#[cfg(feature = "std")]
+use std::fs::File;
+#[cfg(feature = "std")]
+use std::io::{BufReader, Seek, SeekFrom};
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "std")]
+use std::sync::OnceLock;
 
 #[derive(Debug, Snafu)]
 #[snafu(visibility(pub))]
 pub enum Error {
+    #[snafu(display(
+        "multiple keys with size ({} inputs, {} outputs)",
+        num_inputs,
+        num_outputs
+    ))]
     DuplicateKeys {
         num_inputs: usize,
         num_outputs: usize,
     },
+    #[snafu(display("a key set must contain at least one key"))]
     NoKeys,
+    #[snafu(display(
+        "key with size ({} inputs, {} outputs) is indexed under the wrong sort key",
+        num_inputs,
+        num_outputs
+    ))]
+    CorruptIndex {
+        num_inputs: usize,
+        num_outputs: usize,
+    },
+    #[snafu(display("failed to deserialize key set: {}", source))]
+    // Without `std`, SerializationError does not implement snafu's Error trait, so it cannot be
+    // reported as the source of this error, only displayed.
+    #[cfg_attr(not(feature = "std"), snafu(source(false)))]
+    Deserialization { source: SerializationError },
+    #[snafu(display(
+        "{} keys do not match: sizes {:?} have no verifying key, sizes {:?} have no proving key",
+        component,
+        missing_from_verifier,
+        missing_from_prover
+    ))]
+    KeySetMismatch {
+        component: &'static str,
+        missing_from_verifier: Vec<(usize, usize)>,
+        missing_from_prover: Vec<(usize, usize)>,
+    },
+    #[snafu(display(
+        "unsupported key set format version {} (this library reads version {})",
+        version,
+        CURRENT_VERSION
+    ))]
+    UnsupportedVersion { version: u16 },
+    #[snafu(display("checksum mismatch: the serialized key set is corrupt"))]
+    ChecksumMismatch,
+    #[snafu(display(
+        "key declared with size ({} inputs, {} outputs) has size ({} inputs, {} outputs)",
+        declared.0,
+        declared.1,
+        actual.0,
+        actual.1
+    ))]
+    ShapeMismatch {
+        declared: (usize, usize),
+        actual: (usize, usize),
+    },
+    #[snafu(display(
+        "serialized key set claims {} keys, more than the limit of {}",
+        num_keys,
+        max_keys
+    ))]
+    TooManyKeys { num_keys: u64, max_keys: usize },
+    #[cfg(feature = "std")]
+    #[snafu(display("invalid {} keys: {}", component, source))]
+    InvalidComponent {
+        component: &'static str,
+        source: Box<Error>,
+    },
+}
+
+/// The error returned by [KeySet::best_fit_key] when no key is large enough.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BestFitError {
+    /// The size that was requested, as (num_inputs, num_outputs).
+    pub requested: (usize, usize),
+    /// The largest size supported by the KeySet, as (num_inputs, num_outputs), or (0, 0) if the
+    /// KeySet is empty.
+    pub largest_available: (usize, usize),
+    /// Whether the search was abandoned by best_fit_key_with_budget() before it could rule out
+    /// every key. If so, a large enough key may exist after all.
+    pub budget_exhausted: bool,
+}
+
+impl Display for BestFitError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.budget_exhausted {
+            return write!(
+                f,
+                "no key for size ({} inputs, {} outputs) found within the scan budget",
+                self.requested.0, self.requested.1,
+            );
+        }
+        write!(
+            f,
+            "no key for size ({} inputs, {} outputs); the largest available size is ({} inputs, {} \
+             outputs)",
+            self.requested.0,
+            self.requested.1,
+            self.largest_available.0,
+            self.largest_available.1,
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BestFitError {}
+
+/// The result of [KeySet::best_fit_or_split].
+#[derive(Debug)]
+pub enum BestFitPlan<'a, K> {
+    /// A single key is large enough for the requested size. This is the key best_fit_key() would
+    /// return.
+    Fit {
+        num_inputs: usize,
+        num_outputs: usize,
+        key: &'a K,
+    },
+    /// No single key is large enough, so the transaction would have to be split.
+    ///
+    /// `most_inputs` is the key with the most inputs and `most_outputs` is the key with the most
+    /// outputs (each breaking ties in favor of the larger other dimension), as (num_inputs,
+    /// num_outputs, key). Both are `None` if the KeySet is empty.
+    Split {
+        most_inputs: Option<(usize, usize, &'a K)>,
+        most_outputs: Option<(usize, usize, &'a K)>,
+    },
+}
+
+/// The error returned by [KeySet::new_from_results].
+///
+/// Either loading one of the keys failed, or the loaded keys did not form a valid KeySet.
+#[derive(Debug)]
+pub enum KeySetOrLoadError<E> {
+    Load(E),
+    KeySet(Error),
+}
+
+impl<E> From<Error> for KeySetOrLoadError<E> {
+    fn from(err: Error) -> Self {
+        Self::KeySet(err)
+    }
+}
+
+impl<E: Display> Display for KeySetOrLoadError<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Load(err) => write!(f, "failed to load key: {}", err),
+            Self::KeySet(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: std::error::Error + 'static> std::error::Error for KeySetOrLoadError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Load(err) => Some(err),
+            Self::KeySet(err) => Some(err),
+        }
+    }
 }
 
-pub trait SizedKey: CanonicalSerialize + CanonicalDeserialize {
+/// Anything with the size of a circuit: a fixed number of inputs and outputs.
+///
+/// This is the size-only part of [SizedKey], so unlike SizedKey it is also implemented for
+/// references, which lets generic code that only needs sizes accept `&K` without cloning keys.
+pub trait KeyShape {
     fn num_inputs(&self) -> usize;
     fn num_outputs(&self) -> usize;
 }
 
-impl<'a> SizedKey for TransferProvingKey<'a> {
+impl<K: KeyShape + ?Sized> KeyShape for &K {
+    fn num_inputs(&self) -> usize {
+        (**self).num_inputs()
+    }
+
+    fn num_outputs(&self) -> usize {
+        (**self).num_outputs()
+    }
+}
+
+/// A proving or verifying key for a circuit with a fixed number of inputs and outputs.
+///
+/// Keys are stored in a [KeySet] by value and serialized along with it, hence the
+/// `CanonicalSerialize + CanonicalDeserialize` supertraits. For the same reason there is no
+/// implementation for references, since `&K` cannot be deserialized; generic code which only needs
+/// the size of a key should take a [KeyShape] instead.
+///
+/// Before version 0.4, `num_inputs()` and `num_outputs()` were methods of this trait. They are now
+/// methods of [KeyShape], so implementing SizedKey takes an implementation of each trait, and
+/// calling them takes `KeyShape` in scope.
+pub trait SizedKey: KeyShape + CanonicalSerialize + CanonicalDeserialize {}
+
+#[cfg(feature = "std")]
+impl<'a> KeyShape for TransferProvingKey<'a> {
     fn num_inputs(&self) -> usize {
         self.num_input()
     }
@@ -46,7 +260,11 @@ impl<'a> SizedKey for TransferProvingKey<'a> {
     }
 }
 
-impl<'a> SizedKey for FreezeProvingKey<'a> {
+#[cfg(feature = "std")]
+impl<'a> SizedKey for TransferProvingKey<'a> {}
+
+#[cfg(feature = "std")]
+impl<'a> KeyShape for FreezeProvingKey<'a> {
     fn num_inputs(&self) -> usize {
         self.num_input()
     }
@@ -56,12 +274,24 @@ impl<'a> SizedKey for FreezeProvingKey<'a> {
     }
 }
 
-impl SizedKey for TransactionVerifyingKey {
+#[cfg(feature = "std")]
+impl<'a> SizedKey for FreezeProvingKey<'a> {}
+
+/// The size of the CAP mint circuit, as (num_inputs, num_outputs).
+///
+/// Mint keys do not report their size, but the mint circuit always consumes a fee input and
+/// produces a fee change output and the minted record.
+pub const fn mint_shape() -> (usize, usize) {
+    (1, 2)
+}
+
+#[cfg(feature = "std")]
+impl KeyShape for TransactionVerifyingKey {
     fn num_inputs(&self) -> usize {
         match self {
             TransactionVerifyingKey::Transfer(xfr) => xfr.num_input(),
             TransactionVerifyingKey::Freeze(freeze) => freeze.num_input(),
-            TransactionVerifyingKey::Mint(_) => 1,
+            TransactionVerifyingKey::Mint(_) => mint_shape().0,
         }
     }
 
@@ -69,57 +299,288 @@ impl SizedKey for TransactionVerifyingKey {
         match self {
             TransactionVerifyingKey::Transfer(xfr) => xfr.num_output(),
             TransactionVerifyingKey::Freeze(freeze) => freeze.num_output(),
-            TransactionVerifyingKey::Mint(_) => 2,
+            TransactionVerifyingKey::Mint(_) => mint_shape().1,
         }
     }
 }
 
+#[cfg(feature = "std")]
+impl SizedKey for TransactionVerifyingKey {}
+
+/// Serialization bounds required of [KeyOrder::SortKey].
+///
+/// When the `serde` feature is enabled, sort keys must implement `Serialize` and `Deserialize`.
+/// Otherwise, this trait is implemented for every type.
+#[cfg(feature = "serde")]
+pub trait SortKeySerde: Serialize + for<'a> Deserialize<'a> {}
+#[cfg(feature = "serde")]
+impl<T: Serialize + for<'a> Deserialize<'a>> SortKeySerde for T {}
+
+/// Serialization bounds required of [KeyOrder::SortKey].
+///
+/// When the `serde` feature is enabled, sort keys must implement `Serialize` and `Deserialize`.
+/// Otherwise, this trait is implemented for every type.
+#[cfg(not(feature = "serde"))]
+pub trait SortKeySerde {}
+#[cfg(not(feature = "serde"))]
+impl<T> SortKeySerde for T {}
+
 pub trait KeyOrder {
-    type SortKey: Ord
-        + Debug
-        + Clone
-        + Serialize
-        + for<'a> Deserialize<'a>
-        + CanonicalSerialize
-        + CanonicalDeserialize;
+    type SortKey: Ord + Debug + Clone + SortKeySerde + CanonicalSerialize + CanonicalDeserialize;
     fn sort_key(num_inputs: usize, num_outputs: usize) -> Self::SortKey;
+
+    /// Compare two sizes, each given as (num_inputs, num_outputs), in the order used to index a
+    /// KeySet.
+    fn compare(a: (usize, usize), b: (usize, usize)) -> Ordering {
+        Self::sort_key(a.0, a.1).cmp(&Self::sort_key(b.0, b.1))
+    }
+
+    /// A stable, human-readable name for this ordering, for diagnostics.
+    ///
+    /// The default is the Rust type name, which is not guaranteed to be stable across compiler
+    /// versions, so custom orderings should override it.
+    fn name() -> &'static str {
+        core::any::type_name::<Self>()
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct OrderByInputs;
 impl KeyOrder for OrderByInputs {
     type SortKey = (usize, usize);
     fn sort_key(num_inputs: usize, num_outputs: usize) -> Self::SortKey {
         (num_inputs, num_outputs)
     }
+
+    fn name() -> &'static str {
+        "by_inputs"
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct OrderByOutputs;
 impl KeyOrder for OrderByOutputs {
     type SortKey = (usize, usize);
     fn sort_key(num_inputs: usize, num_outputs: usize) -> Self::SortKey {
         (num_outputs, num_inputs)
     }
+
+    fn name() -> &'static str {
+        "by_outputs"
+    }
+}
+
+/// Order keys by their total size, `num_inputs + num_outputs`, breaking ties by number of inputs
+/// and then number of outputs.
+///
+/// Any key which is at least as large as a given size in both dimensions has at least as large a
+/// total, so best_fit_key() finds all candidate keys in its range, as it does for the other
+/// orderings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OrderBySum;
+impl KeyOrder for OrderBySum {
+    type SortKey = (usize, usize, usize);
+    fn sort_key(num_inputs: usize, num_outputs: usize) -> Self::SortKey {
+        (num_inputs + num_outputs, num_inputs, num_outputs)
+    }
+
+    fn name() -> &'static str {
+        "by_sum"
+    }
+}
+
+/// Order keys by their larger dimension, `max(num_inputs, num_outputs)`, breaking ties by number
+/// of inputs and then number of outputs.
+///
+/// With this ordering, best_fit_key() prefers the key with the smallest dominating dimension. Note
+/// that the lower bound of the range searched by best_fit_key() only constrains the larger of the
+/// two requested dimensions, so it is more conservative than under `OrderByInputs`: the range may
+/// include many keys which are too small in the other dimension, which are skipped by the scan.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OrderByMax;
+impl KeyOrder for OrderByMax {
+    type SortKey = (usize, usize, usize);
+    fn sort_key(num_inputs: usize, num_outputs: usize) -> Self::SortKey {
+        (num_inputs.max(num_outputs), num_inputs, num_outputs)
+    }
+
+    fn name() -> &'static str {
+        "by_max"
+    }
+}
+
+/// Define a zero-sized [KeyOrder] from an inline sort key expression.
+///
+/// ```ignore
+/// key_order!(pub OrderByProduct, |num_inputs, num_outputs| -> (usize, usize, usize) {
+///     (num_inputs * num_outputs, num_inputs, num_outputs)
+/// });
+/// let keys: KeySet<TransferProvingKey, OrderByProduct> = KeySet::new(keys)?;
+/// ```
+///
+/// The name() of the generated ordering is the name of the type. As with any `KeyOrder`, the sort
+/// key must be distinct for distinct sizes, or KeySet::new() will
+/// reject keys of different sizes as duplicates. It must also never be smaller for a key which is
+/// at least as large in both dimensions, or best_fit_key() may miss keys which fit. The generated
+/// type does not implement `Serialize` or `Deserialize`, so it cannot be used as the ordering of a
+/// serde-serialized ProverKeySet or VerifierKeySet.
+#[macro_export]
+macro_rules! key_order {
+    ($(#[$attr:meta])* $vis:vis $name:ident, |$num_inputs:ident, $num_outputs:ident| -> $sort_key:ty $body:block) => {
+        $(#[$attr])*
+        #[derive(Clone, Debug, PartialEq, Eq)]
+        $vis struct $name;
+        impl $crate::KeyOrder for $name {
+            type SortKey = $sort_key;
+            fn sort_key($num_inputs: usize, $num_outputs: usize) -> Self::SortKey $body
+            fn name() -> &'static str {
+                stringify!($name)
+            }
+        }
+    };
+}
+
+/// A `serde_with` adapter for serializing an index of keys, such as the one inside a [KeySet].
+///
+/// In human-readable formats like JSON, the index is written as a map from the size of each key,
+/// as a string `"{num_inputs}-{num_outputs}"`, to the key, so that hand-edited files are easy to
+/// read. The sort keys are recomputed from the sizes when deserializing, and a size which does not
+/// match its key is an error. In binary formats, the index is written as a sequence of (sort key,
+/// key) pairs, which is what commitments are computed over. In either form, more than one key with
+/// the same sort key is an error.
+///
+/// Older versions of this crate wrote human-readable formats as a sequence of pairs too, so both
+/// forms are accepted when deserializing from a human-readable format.
+#[cfg(feature = "serde")]
+pub struct ShapeKeys<Order>(PhantomData<Order>);
+
+#[cfg(feature = "serde")]
+impl<K: SizedKey + Serialize, Order: KeyOrder> SerializeAs<BTreeMap<Order::SortKey, K>>
+    for ShapeKeys<Order>
+{
+    fn serialize_as<S: Serializer>(
+        source: &BTreeMap<Order::SortKey, K>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_map(
+                source
+                    .values()
+                    .map(|key| (format!("{}-{}", key.num_inputs(), key.num_outputs()), key)),
+            )
+        } else {
+            <Vec<(Same, Same)>>::serialize_as(source, serializer)
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, K: SizedKey + Deserialize<'de>, Order: KeyOrder>
+    DeserializeAs<'de, BTreeMap<Order::SortKey, K>> for ShapeKeys<Order>
+{
+    fn deserialize_as<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<BTreeMap<Order::SortKey, K>, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(ShapeKeysVisitor::<K, Order>(PhantomData))
+        } else {
+            // The same sequence of pairs that `Vec<(Same, Same)>` reads, but with duplicates
+            // rejected.
+            deserializer.deserialize_seq(ShapeKeysVisitor::<K, Order>(PhantomData))
+        }
+    }
+}
+
+/// Deserializes any of the forms of an index accepted by [ShapeKeys].
+#[cfg(feature = "serde")]
+struct ShapeKeysVisitor<K, Order>(PhantomData<(K, Order)>);
+
+#[cfg(feature = "serde")]
+impl<'de, K: SizedKey + Deserialize<'de>, Order: KeyOrder> serde::de::Visitor<'de>
+    for ShapeKeysVisitor<K, Order>
+{
+    type Value = BTreeMap<Order::SortKey, K>;
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("a map from key sizes to keys, or a sequence of (sort key, key) pairs")
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut keys = BTreeMap::new();
+        while let Some((shape, key)) = map.next_entry::<String, K>()? {
+            let size = parse_shape(&shape)
+                .ok_or_else(|| serde::de::Error::custom(format!("invalid key size {:?}", shape)))?;
+            if size != (key.num_inputs(), key.num_outputs()) {
+                return Err(serde::de::Error::custom(format!(
+                    "key indexed under size {:?} has size ({}, {})",
+                    shape,
+                    key.num_inputs(),
+                    key.num_outputs()
+                )));
+            }
+            if keys.insert(Order::sort_key(size.0, size.1), key).is_some() {
+                return Err(duplicate_keys(size));
+            }
+        }
+        Ok(keys)
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut keys = BTreeMap::new();
+        while let Some((sort_key, key)) = seq.next_element::<(Order::SortKey, K)>()? {
+            let size = (key.num_inputs(), key.num_outputs());
+            if keys.insert(sort_key, key).is_some() {
+                return Err(duplicate_keys(size));
+            }
+        }
+        Ok(keys)
+    }
+}
+
+/// The error for a human-readable index which lists more than one key with the same sort key as
+/// `size`, with the same message as [Error::DuplicateKeys].
+#[cfg(feature = "serde")]
+fn duplicate_keys<E: serde::de::Error>((num_inputs, num_outputs): (usize, usize)) -> E {
+    E::custom(Error::DuplicateKeys {
+        num_inputs,
+        num_outputs,
+    })
+}
+
+/// Parse a size written by [ShapeKeys], like `"3-2"`, as (num_inputs, num_outputs).
+#[cfg(feature = "serde")]
+fn parse_shape(shape: &str) -> Option<(usize, usize)> {
+    let (num_inputs, num_outputs) = shape.split_once('-')?;
+    Some((num_inputs.parse().ok()?, num_outputs.parse().ok()?))
 }
 
-#[serde_as]
-#[derive(
-    Debug,
-    Default,
-    Clone,
-    Serialize,
-    Deserialize,
-    CanonicalSerialize,
-    CanonicalDeserialize,
-    PartialEq,
-    Eq,
+/// A collection of keys for circuits of different sizes, indexed by size according to `Order`.
+///
+/// A KeySet created by new() always contains at least one key, but the `Default` KeySet is empty,
+/// as is a KeySet emptied with remove(), retain(), clear() and the like. Most methods work on an
+/// empty KeySet, but max_size(), min_size(), input_range() and output_range() panic, since there
+/// is no size to return. Use try_max_size(), try_min_size(), try_input_range() and
+/// try_output_range() when a KeySet may be empty.
+///
+/// The serialization of a KeySet is deterministic: keys are always written in sort order,
+/// regardless of the order in which they were inserted, so two KeySets with the same keys have
+/// byte-for-byte identical canonical and serde encodings. Commitments to key sets depend on this,
+/// so the index must remain an ordered map.
+#[cfg_attr(feature = "serde", serde_as)]
+#[derive(Clone, CanonicalSerialize, CanonicalDeserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound = "K: Serialize + for<'a> Deserialize<'a>")
 )]
-#[serde(bound = "K: Serialize + for<'a> Deserialize<'a>")]
 pub struct KeySet<K: SizedKey, Order: KeyOrder = OrderByInputs> {
     // serde_json does not support maps where the keys are not Strings (or easily convertible
-    // to/from Strings) so we serialize this map as a sequence of key-value pairs.
-    #[serde_as(as = "Vec<(_, _)>")]
+    // to/from Strings), so in human-readable formats this map is keyed by size strings instead.
+    #[cfg_attr(feature = "serde", serde_as(as = "ShapeKeys<Order>"))]
     keys: BTreeMap<Order::SortKey, K>,
 }
 
@@ -127,16 +588,72 @@ impl<K: SizedKey, Order: KeyOrder> KeySet<K, Order> {
     /// Create a new KeySet with the keys in an iterator. `keys` must contain at least one key,
     /// and it must not contain two keys with the same size.
     pub fn new(keys: impl Iterator<Item = K>) -> Result<Self, Error> {
+        let mut set = Self::default();
+        for key in keys {
+            set.insert(key)?;
+        }
+        if set.is_empty() {
+            return Err(Error::NoKeys);
+        }
+        Ok(set)
+    }
+
+    /// Create a new KeySet from keys paired with the sizes they are expected to have, as
+    /// ((num_inputs, num_outputs), key).
+    ///
+    /// Fails with [Error::ShapeMismatch] if any key's actual size differs from its declared size,
+    /// and otherwise with the same errors as new().
+    pub fn from_shaped_pairs(
+        pairs: impl Iterator<Item = ((usize, usize), K)>,
+    ) -> Result<Self, Error> {
+        Self::new_from_results(pairs.map(|(declared, key)| {
+            let actual = (key.num_inputs(), key.num_outputs());
+            if declared == actual {
+                Ok(key)
+            } else {
+                Err(Error::ShapeMismatch { declared, actual })
+            }
+        }))
+        .map_err(|err| match err {
+            KeySetOrLoadError::Load(err) | KeySetOrLoadError::KeySet(err) => err,
+        })
+    }
+
+    /// Create a new KeySet from an iterator of keys which may have failed to load.
+    ///
+    /// This stops at the first load failure, without consuming the rest of the iterator, and
+    /// otherwise fails with the same errors as new().
+    pub fn new_from_results<E>(
+        keys: impl Iterator<Item = Result<K, E>>,
+    ) -> Result<Self, KeySetOrLoadError<E>> {
+        let mut set = Self::default();
+        for key in keys {
+            set.insert(key.map_err(KeySetOrLoadError::Load)?)?;
+        }
+        if set.is_empty() {
+            return Err(Error::NoKeys.into());
+        }
+        Ok(set)
+    }
+
+    /// Create a new KeySet with the keys in an iterator, keeping only the largest key of each size.
+    ///
+    /// When two keys have the same size, the one with the larger canonical serialization is kept,
+    /// and if they serialize to the same number of bytes, the first one is kept. `keys` must still
+    /// contain at least one key.
+    pub fn new_keeping_largest(keys: impl Iterator<Item = K>) -> Result<Self, Error> {
         let mut map = BTreeMap::new();
         for key in keys {
-            let sort_key = Order::sort_key(key.num_inputs(), key.num_outputs());
-            if map.contains_key(&sort_key) {
-                return Err(Error::DuplicateKeys {
-                    num_inputs: key.num_inputs(),
-                    num_outputs: key.num_outputs(),
-                });
+            match map.entry(Order::sort_key(key.num_inputs(), key.num_outputs())) {
+                btree_map::Entry::Vacant(e) => {
+                    e.insert(key);
+                }
+                btree_map::Entry::Occupied(mut e) => {
+                    if key.serialized_size() > e.get().serialized_size() {
+                        e.insert(key);
+                    }
+                }
             }
-            map.insert(sort_key, key);
         }
         if map.is_empty() {
             return Err(Error::NoKeys);
@@ -144,86 +661,4675 @@ impl<K: SizedKey, Order: KeyOrder> KeySet<K, Order> {
         Ok(Self { keys: map })
     }
 
+    /// Create a new KeySet with the keys in an iterator, which is expected to yield about
+    /// `expected_len` keys.
+    ///
+    /// `BTreeMap` has no notion of capacity, so this is equivalent to new(). It exists so that
+    /// callers can document the expected size of the KeySet at construction.
+    pub fn new_with_hint(
+        keys: impl Iterator<Item = K>,
+        expected_len: usize,
+    ) -> Result<Self, Error> {
+        let _ = expected_len;
+        Self::new(keys)
+    }
+
+    /// Create a new KeySet from anything that can be iterated over.
+    ///
+    /// This is a non-panicking alternative to the `FromIterator` implementation: it fails with
+    /// the same errors as new().
+    pub fn try_from_iter(iter: impl IntoIterator<Item = K>) -> Result<Self, Error> {
+        Self::new(iter.into_iter())
+    }
+
+    /// Add a key to this KeySet. Fails if the KeySet already contains a key with the same size.
+    pub fn insert(&mut self, key: K) -> Result<(), Error> {
+        let sort_key = Order::sort_key(key.num_inputs(), key.num_outputs());
+        if self.keys.contains_key(&sort_key) {
+            return Err(Error::DuplicateKeys {
+                num_inputs: key.num_inputs(),
+                num_outputs: key.num_outputs(),
+            });
+        }
+        self.keys.insert(sort_key, key);
+        Ok(())
+    }
+
     /// Get the largest size supported by this KeySet.
     ///
-    /// Panics if there are no keys in the KeySet. Since new() requires at least one key, this
-    /// can only happen if the KeySet is corrupt (for example, it was deserialized from a
-    /// corrupted file).
+    /// Panics if there are no keys in the KeySet. A KeySet built by new() always has a key, but the
+    /// `Default` KeySet does not, and neither does one emptied by remove(), retain(), clear(),
+    /// retain_largest_n() or split_at_size(). This method keeps the panicking signature it has
+    /// always had, since most KeySets come from new() and callers rely on getting a size
+    /// unconditionally; use try_max_size() when the KeySet may be empty.
     pub fn max_size(&self) -> (usize, usize) {
-        let key = &self.keys.iter().next_back().unwrap().1;
-        (key.num_inputs(), key.num_outputs())
+        self.try_max_size()
+            .expect("KeySet must contain at least one key")
     }
 
-    pub fn key_for_size(&self, num_inputs: usize, num_outputs: usize) -> Option<&K> {
+    /// Get the largest size supported by this KeySet, or `None` if there are no keys.
+    ///
+    /// This is a non-panicking version of max_size(), which can be used to handle an empty KeySet
+    /// gracefully.
+    pub fn try_max_size(&self) -> Option<(usize, usize)> {
+        let (num_inputs, num_outputs, _) = self.last()?;
+        Some((num_inputs, num_outputs))
+    }
+
+    /// Suggest a size for a new key which this KeySet cannot yet support: one more input and one
+    /// more output than max_size().
+    ///
+    /// This is advisory only, for capacity planning: it does not check that the suggested circuit
+    /// size is supported by any parameters. Under the orderings in this crate, the suggested size
+    /// always sorts after max_size(), so the KeySet never already contains it. Returns `None` if
+    /// the KeySet is empty.
+    pub fn suggest_next_shape(&self) -> Option<(usize, usize)> {
+        let (num_inputs, num_outputs) = self.try_max_size()?;
+        Some((num_inputs + 1, num_outputs + 1))
+    }
+
+    /// Get the smallest size supported by this KeySet.
+    ///
+    /// "Smallest" is defined by the sort key of `Order`, so for example, under `OrderByOutputs`
+    /// this is the size of the key with the fewest outputs (and, among those, the fewest inputs).
+    ///
+    /// Panics if there are no keys in the KeySet, just like max_size(), and for the same reason;
+    /// use try_min_size() when the KeySet may be empty.
+    pub fn min_size(&self) -> (usize, usize) {
+        self.try_min_size()
+            .expect("KeySet must contain at least one key")
+    }
+
+    /// Get the smallest size supported by this KeySet, or `None` if there are no keys.
+    ///
+    /// As with min_size(), "smallest" is defined by the sort key of `Order`.
+    pub fn try_min_size(&self) -> Option<(usize, usize)> {
+        let (num_inputs, num_outputs, _) = self.first()?;
+        Some((num_inputs, num_outputs))
+    }
+
+    /// The first key in sort order, along with its size, or `None` if the KeySet is empty.
+    ///
+    /// The size is that of min_size().
+    pub fn first(&self) -> Option<(usize, usize, &K)> {
+        let (_, key) = self.keys.iter().next()?;
+        Some((key.num_inputs(), key.num_outputs(), key))
+    }
+
+    /// The last key in sort order, along with its size, or `None` if the KeySet is empty.
+    ///
+    /// The size is that of max_size().
+    pub fn last(&self) -> Option<(usize, usize, &K)> {
+        let (_, key) = self.keys.iter().next_back()?;
+        Some((key.num_inputs(), key.num_outputs(), key))
+    }
+
+    /// The smallest and largest number of inputs of any key in this KeySet, as (min, max).
+    ///
+    /// Unlike min_size() and max_size(), this considers each dimension independently of `Order`,
+    /// so the minimum and maximum may come from different keys.
+    ///
+    /// Panics if there are no keys in the KeySet, just like max_size(); use try_input_range() when
+    /// the KeySet may be empty.
+    pub fn input_range(&self) -> (usize, usize) {
+        self.try_input_range()
+            .expect("KeySet must contain at least one key")
+    }
+
+    /// The smallest and largest number of inputs of any key in this KeySet, or `None` if there are
+    /// no keys.
+    pub fn try_input_range(&self) -> Option<(usize, usize)> {
+        dimension_range(self.iter().map(|key| key.num_inputs()))
+    }
+
+    /// The smallest and largest number of outputs of any key in this KeySet, as (min, max).
+    ///
+    /// Like input_range(), this is independent of `Order`.
+    ///
+    /// Panics if there are no keys in the KeySet, just like max_size(); use try_output_range() when
+    /// the KeySet may be empty.
+    pub fn output_range(&self) -> (usize, usize) {
+        self.try_output_range()
+            .expect("KeySet must contain at least one key")
+    }
+
+    /// The smallest and largest number of outputs of any key in this KeySet, or `None` if there
+    /// are no keys.
+    pub fn try_output_range(&self) -> Option<(usize, usize)> {
+        dimension_range(self.iter().map(|key| key.num_outputs()))
+    }
+
+    /// Return the key whose size is (num_inputs, num_outputs).
+    pub fn get(&self, num_inputs: usize, num_outputs: usize) -> Option<&K> {
         self.keys.get(&Order::sort_key(num_inputs, num_outputs))
     }
 
+    #[deprecated(note = "key_for_size is identical to get, which should be used instead")]
+    pub fn key_for_size(&self, num_inputs: usize, num_outputs: usize) -> Option<&K> {
+        self.get(num_inputs, num_outputs)
+    }
+
     /// Return the smallest key whose size is at least (num_inputs, num_outputs). If no such key
-    /// is available, the error contains the largest size that could have been supported.
+    /// is available, the error contains the requested size and the largest size that could have
+    /// been supported.
+    ///
+    /// The sort key only bounds the search in one dimension, so in the worst case this scans every
+    /// key which is larger than the requested size in sort order, which is linear in the size of
+    /// the KeySet. Key sets are small in practice (one key per supported circuit), so this is not
+    /// worth maintaining a secondary index, which every method that changes the keys would have to
+    /// keep up to date. Use best_fit_key_with_budget() to bound the scan instead.
     pub fn best_fit_key(
         &self,
         num_inputs: usize,
         num_outputs: usize,
-    ) -> Result<(usize, usize, &K), (usize, usize)> {
+    ) -> Result<(usize, usize, &K), BestFitError> {
+        self.range_fit(num_inputs, num_outputs)
+            .next()
+            .ok_or_else(|| BestFitError {
+                requested: (num_inputs, num_outputs),
+                largest_available: self.try_max_size().unwrap_or((0, 0)),
+                budget_exhausted: false,
+            })
+    }
+
+    /// Return the size of the key best_fit_key() would return, without borrowing the key.
+    pub fn peek_best_fit_size(
+        &self,
+        num_inputs: usize,
+        num_outputs: usize,
+    ) -> Result<(usize, usize), BestFitError> {
+        let (key_inputs, key_outputs, _) = self.best_fit_key(num_inputs, num_outputs)?;
+        Ok((key_inputs, key_outputs))
+    }
+
+    /// Like best_fit_key(), but if no single key is large enough, describe the largest keys in
+    /// each dimension instead of failing, so the caller can decide how to split the transaction.
+    pub fn best_fit_or_split(&self, num_inputs: usize, num_outputs: usize) -> BestFitPlan<'_, K> {
+        if let Some((num_inputs, num_outputs, key)) = self.range_fit(num_inputs, num_outputs).next()
+        {
+            return BestFitPlan::Fit {
+                num_inputs,
+                num_outputs,
+                key,
+            };
+        }
+        let sized = || {
+            self.iter()
+                .map(|key| (key.num_inputs(), key.num_outputs(), key))
+        };
+        BestFitPlan::Split {
+            most_inputs: sized()
+                .max_by_key(|&(num_inputs, num_outputs, _)| (num_inputs, num_outputs)),
+            most_outputs: sized()
+                .max_by_key(|&(num_inputs, num_outputs, _)| (num_outputs, num_inputs)),
+        }
+    }
+
+    /// Like best_fit_key(), but gives up after examining `max_scan` keys.
+    ///
+    /// This bounds the cost of a lookup in the worst case, where most of the keys after the
+    /// requested size in sort order are too small in the other dimension. If the budget runs out,
+    /// the error has `budget_exhausted` set, even though a larger key may exist.
+    pub fn best_fit_key_with_budget(
+        &self,
+        num_inputs: usize,
+        num_outputs: usize,
+        max_scan: usize,
+    ) -> Result<(usize, usize, &K), BestFitError> {
+        let mut candidates = scan_fit::<Order, _>(&self.keys, num_inputs, num_outputs, key_size);
+        if let Some((_, (key_inputs, key_outputs), key)) =
+            candidates.by_ref().take(max_scan).flatten().next()
+        {
+            return Ok((key_inputs, key_outputs, key));
+        }
+        Err(BestFitError {
+            requested: (num_inputs, num_outputs),
+            largest_available: self.try_max_size().unwrap_or((0, 0)),
+            // If there are keys left in the range, we stopped because of the budget.
+            budget_exhausted: candidates.next().is_some(),
+        })
+    }
+
+    /// Like best_fit_key(), but returns a mutable reference to the key.
+    ///
+    /// As with get_mut(), callers must not change the num_inputs() or num_outputs() of the key.
+    ///
+    /// On failure this returns the same [BestFitError] as best_fit_key(), rather than just the
+    /// largest available size as a `(usize, usize)`, so that the two methods can be used
+    /// interchangeably.
+    pub fn best_fit_key_mut(
+        &mut self,
+        num_inputs: usize,
+        num_outputs: usize,
+    ) -> Result<(usize, usize, &mut K), BestFitError> {
+        // Compute the error up front, since it can't borrow `self` while the range is borrowed
+        // mutably.
+        let err = BestFitError {
+            requested: (num_inputs, num_outputs),
+            largest_available: self.try_max_size().unwrap_or((0, 0)),
+            budget_exhausted: false,
+        };
         self.keys
-            .range((
+            .range_mut((
                 Included(Order::sort_key(num_inputs, num_outputs)),
                 Unbounded,
             ))
-            // We are not guaranteed that everything in this range has `inputs >= num_inputs` _and_
-            // `outputs >= num_outputs`. For example, if `Order` is `OrderByInputs`, everything in
-            // the range has `inputs >= num_inputs`, but not necessarily `outputs >= num_outputs`,
-            // since, e.g. (3, 1) >= (2, 2) even though 1 < 2. Therefore, we need to iterate over
-            // the range to find the first key that satisfies both constraints.
-            .find_map(|(_, key)| {
-                let key_inputs = key.num_inputs();
-                let key_outputs = key.num_outputs();
-                if key_inputs >= num_inputs && key_outputs >= num_outputs {
-                    Some((key_inputs, key_outputs, key))
-                } else {
-                    None
-                }
+            .find(|(_, key)| key.num_inputs() >= num_inputs && key.num_outputs() >= num_outputs)
+            .map(|(_, key)| (key.num_inputs(), key.num_outputs(), key))
+            .ok_or(err)
+    }
+
+    /// Iterate over all keys whose size is at least (num_inputs, num_outputs), in sort order.
+    ///
+    /// The first item yielded, if any, is the key returned by best_fit_key().
+    pub fn range_fit(
+        &self,
+        num_inputs: usize,
+        num_outputs: usize,
+    ) -> impl Iterator<Item = (usize, usize, &K)> {
+        scan_fit::<Order, _>(&self.keys, num_inputs, num_outputs, key_size)
+            .flatten()
+            .map(|(_, (key_inputs, key_outputs), key)| (key_inputs, key_outputs, key))
+    }
+
+    /// Return the first key in sort order with exactly `num_outputs` outputs and at least
+    /// `num_inputs` inputs.
+    ///
+    /// This is for circuits which can be padded with extra inputs but not extra outputs. Unlike
+    /// best_fit_key(), it never returns a key with more outputs than requested.
+    pub fn best_fit_inputs_exact_outputs(
+        &self,
+        num_inputs: usize,
+        num_outputs: usize,
+    ) -> Option<(usize, usize, &K)> {
+        self.range_fit(num_inputs, num_outputs)
+            .find(|&(_, key_outputs, _)| key_outputs == num_outputs)
+    }
+
+    /// Return the first key in sort order with exactly `num_inputs` inputs and at least
+    /// `num_outputs` outputs.
+    ///
+    /// This is the counterpart of best_fit_inputs_exact_outputs() for circuits which can be padded
+    /// with extra outputs but not extra inputs.
+    pub fn best_fit_outputs_exact_inputs(
+        &self,
+        num_inputs: usize,
+        num_outputs: usize,
+    ) -> Option<(usize, usize, &K)> {
+        self.range_fit(num_inputs, num_outputs)
+            .find(|&(key_inputs, _, _)| key_inputs == num_inputs)
+    }
+
+    /// Return the key whose size is at least (num_inputs, num_outputs) with the least excess
+    /// capacity, measured as `(key_inputs - num_inputs) + (key_outputs - num_outputs)`.
+    ///
+    /// Unlike best_fit_key(), this does not depend on `Order`, so it can give a tighter fit when
+    /// the ordering over-provisions one dimension. Ties are broken in favor of the key which comes
+    /// first in sort order.
+    pub fn nearest_key(&self, num_inputs: usize, num_outputs: usize) -> Option<(usize, usize, &K)> {
+        self.range_fit(num_inputs, num_outputs)
+            .min_by_key(|(key_inputs, key_outputs, _)| {
+                (key_inputs - num_inputs) + (key_outputs - num_outputs)
             })
-            .ok_or_else(|| self.max_size())
+    }
+
+    /// Return the largest key in this KeySet, along with its size, or `None` if there are no keys.
+    ///
+    /// The size of the returned key is max_size().
+    pub fn worst_fit_key(&self) -> Option<(usize, usize, &K)> {
+        self.last()
+    }
+
+    /// Return the greatest key whose sort key is at most that of (num_inputs, num_outputs).
+    ///
+    /// This follows the sort order of `Order`, not the sizes of the keys in each dimension, so the
+    /// returned key may have more inputs or outputs than requested. See best_fit_key() for a
+    /// lookup which respects both dimensions.
+    pub fn lower_bound_key(
+        &self,
+        num_inputs: usize,
+        num_outputs: usize,
+    ) -> Option<(usize, usize, &K)> {
+        let (_, key) = self
+            .keys
+            .range(..=Order::sort_key(num_inputs, num_outputs))
+            .next_back()?;
+        Some((key.num_inputs(), key.num_outputs(), key))
+    }
+
+    /// Return the least key whose sort key is at least that of (num_inputs, num_outputs).
+    ///
+    /// Like lower_bound_key(), this follows the sort order of `Order`, not the sizes of the keys
+    /// in each dimension.
+    pub fn upper_bound_key(
+        &self,
+        num_inputs: usize,
+        num_outputs: usize,
+    ) -> Option<(usize, usize, &K)> {
+        let (_, key) = self
+            .keys
+            .range(Order::sort_key(num_inputs, num_outputs)..)
+            .next()?;
+        Some((key.num_inputs(), key.num_outputs(), key))
+    }
+
+    /// Iterate over the keys whose sort key is at least that of `low` and less than that of
+    /// `high`, in sort order.
+    ///
+    /// Both bounds are sizes, as (num_inputs, num_outputs), but like lower_bound_key(), they are
+    /// compared by their sort keys under `Order`, not dimension by dimension. If `high` comes
+    /// before `low` in sort order, the range is empty.
+    pub fn get_range(
+        &self,
+        low: (usize, usize),
+        high: (usize, usize),
+    ) -> impl Iterator<Item = (usize, usize, &K)> {
+        let low = Order::sort_key(low.0, low.1);
+        // BTreeMap::range panics if the end of the range is before its start.
+        let high = Order::sort_key(high.0, high.1).max(low.clone());
+        self.keys
+            .range(low..high)
+            .map(|(_, key)| (key.num_inputs(), key.num_outputs(), key))
     }
 
     /// Return the key whose size is (num_inputs, num_outputs).
+    ///
+    /// This is the same as get(), and is named for symmetry with best_fit_key().
     pub fn exact_fit_key(&self, num_inputs: usize, num_outputs: usize) -> Option<&K> {
-        self.keys.get(&Order::sort_key(num_inputs, num_outputs))
+        self.get(num_inputs, num_outputs)
+    }
+
+    /// Whether this KeySet contains a key whose size is exactly (num_inputs, num_outputs).
+    pub fn contains_size(&self, num_inputs: usize, num_outputs: usize) -> bool {
+        self.keys
+            .contains_key(&Order::sort_key(num_inputs, num_outputs))
+    }
+
+    /// Whether this KeySet has a key whose size is at least (num_inputs, num_outputs), that is,
+    /// whether best_fit_key() would succeed.
+    pub fn covers_size(&self, num_inputs: usize, num_outputs: usize) -> bool {
+        self.range_fit(num_inputs, num_outputs).next().is_some()
+    }
+
+    /// Get a mutable reference to the key whose size is (num_inputs, num_outputs).
+    ///
+    /// The key is indexed by its size, so callers must not change the key in a way that changes
+    /// its num_inputs() or num_outputs(). Doing so would corrupt the KeySet.
+    pub fn get_mut(&mut self, num_inputs: usize, num_outputs: usize) -> Option<&mut K> {
+        self.keys.get_mut(&Order::sort_key(num_inputs, num_outputs))
     }
 
     pub fn iter(&self) -> impl Iterator<Item = &K> {
         self.keys.values()
     }
-}
 
-impl<K: SizedKey, Order: KeyOrder> FromIterator<K> for KeySet<K, Order> {
-    fn from_iter<T: IntoIterator<Item = K>>(iter: T) -> Self {
-        Self::new(iter.into_iter()).unwrap()
+    /// Group the keys in this KeySet by their number of inputs.
+    ///
+    /// Within each group, keys are in sort order.
+    pub fn keys_by_inputs(&self) -> BTreeMap<usize, Vec<&K>> {
+        let mut groups = BTreeMap::<usize, Vec<&K>>::new();
+        for key in self.iter() {
+            groups.entry(key.num_inputs()).or_default().push(key);
+        }
+        groups
     }
-}
 
-#[derive(
-    Debug, Clone, CanonicalSerialize, CanonicalDeserialize, Serialize, Deserialize, PartialEq,
-)]
-pub struct ProverKeySet<'a, Order: KeyOrder = OrderByInputs> {
-    pub mint: MintProvingKey<'a>,
-    pub xfr: KeySet<TransferProvingKey<'a>, Order>,
-    pub freeze: KeySet<FreezeProvingKey<'a>, Order>,
+    /// Group the keys in this KeySet by their number of outputs.
+    ///
+    /// Within each group, keys are in sort order.
+    pub fn keys_by_outputs(&self) -> BTreeMap<usize, Vec<&K>> {
+        let mut groups = BTreeMap::<usize, Vec<&K>>::new();
+        for key in self.iter() {
+            groups.entry(key.num_outputs()).or_default().push(key);
+        }
+        groups
+    }
+
+    /// The number of keys in this KeySet with each number of inputs.
+    pub fn input_histogram(&self) -> BTreeMap<usize, usize> {
+        let mut counts = BTreeMap::new();
+        for key in self.iter() {
+            *counts.entry(key.num_inputs()).or_default() += 1;
+        }
+        counts
+    }
+
+    /// The number of keys in this KeySet with each number of outputs.
+    pub fn output_histogram(&self) -> BTreeMap<usize, usize> {
+        let mut counts = BTreeMap::new();
+        for key in self.iter() {
+            *counts.entry(key.num_outputs()).or_default() += 1;
+        }
+        counts
+    }
+
+    /// A grid marking which sizes have a key, indexed as `grid[num_inputs][num_outputs]`.
+    ///
+    /// The grid has one row for each number of inputs up to the largest in the KeySet, and one
+    /// column for each number of outputs up to the largest in the KeySet, including 0 in both
+    /// dimensions. For an empty KeySet, the grid is empty.
+    pub fn coverage_grid(&self) -> Vec<Vec<bool>> {
+        let (max_inputs, max_outputs) = match (self.try_input_range(), self.try_output_range()) {
+            (Some((_, max_inputs)), Some((_, max_outputs))) => (max_inputs, max_outputs),
+            _ => return Vec::new(),
+        };
+        let mut grid = vec![vec![false; max_outputs + 1]; max_inputs + 1];
+        for key in self.iter() {
+            grid[key.num_inputs()][key.num_outputs()] = true;
+        }
+        grid
+    }
+
+    /// The sizes of the keys in this KeySet which are not in `other`, in sort order.
+    ///
+    /// Sizes are compared as (num_inputs, num_outputs), so `other` need not use the same ordering.
+    pub fn shape_difference<O2: KeyOrder>(&self, other: &KeySet<K, O2>) -> Vec<(usize, usize)> {
+        self.iter_sizes()
+            .map(|(size, _)| size)
+            .filter(|&(num_inputs, num_outputs)| !other.contains_size(num_inputs, num_outputs))
+            .collect()
+    }
+
+    /// The sizes of the keys in both this KeySet and `other`, in sort order.
+    ///
+    /// Sizes are compared as (num_inputs, num_outputs), so `other` need not use the same ordering.
+    pub fn shape_intersection<O2: KeyOrder>(&self, other: &KeySet<K, O2>) -> Vec<(usize, usize)> {
+        self.iter_sizes()
+            .map(|(size, _)| size)
+            .filter(|&(num_inputs, num_outputs)| other.contains_size(num_inputs, num_outputs))
+            .collect()
+    }
+
+    /// Whether this KeySet contains a key of every size in `other`.
+    ///
+    /// Sizes must match exactly; a larger key which could be used via best_fit_key() does not
+    /// count. As with shape_difference(), `other` need not use the same ordering.
+    pub fn is_superset_of<O2: KeyOrder>(&self, other: &KeySet<K, O2>) -> bool {
+        other
+            .iter_sizes()
+            .all(|((num_inputs, num_outputs), _)| self.contains_size(num_inputs, num_outputs))
+    }
+
+    /// Whether any key in this KeySet has a size (num_inputs, num_outputs) satisfying `f`.
+    ///
+    /// For example, `set.any_shape(|_, num_outputs| num_outputs == 2)` checks whether the set
+    /// supports any circuit with 2 outputs.
+    pub fn any_shape<F: Fn(usize, usize) -> bool>(&self, f: F) -> bool {
+        self.iter_sizes()
+            .any(|((num_inputs, num_outputs), _)| f(num_inputs, num_outputs))
+    }
+
+    /// Whether every key in this KeySet has a size (num_inputs, num_outputs) satisfying `f`.
+    ///
+    /// This is vacuously true for an empty KeySet.
+    pub fn all_shapes<F: Fn(usize, usize) -> bool>(&self, f: F) -> bool {
+        self.iter_sizes()
+            .all(|((num_inputs, num_outputs), _)| f(num_inputs, num_outputs))
+    }
+
+    /// The number of keys in this KeySet whose size (num_inputs, num_outputs) satisfies `f`.
+    pub fn count_shapes<F: Fn(usize, usize) -> bool>(&self, f: F) -> usize {
+        self.iter_sizes()
+            .filter(|&((num_inputs, num_outputs), _)| f(num_inputs, num_outputs))
+            .count()
+    }
+
+    /// Convert this KeySet into a [SharedKeySet], whose clones share their keys.
+    pub fn into_shared(self) -> SharedKeySet<K, Order> {
+        SharedKeySet {
+            keys: self
+                .keys
+                .into_iter()
+                .map(|(sort_key, key)| (sort_key, Arc::new(key)))
+                .collect(),
+        }
+    }
+
+    /// Format this KeySet with all of its keys, unlike its concise `Debug` representation.
+    pub fn debug_full(&self) -> DebugFull<'_, K, Order> {
+        DebugFull(self)
+    }
+
+    /// Iterate over mutable references to the keys in this KeySet.
+    ///
+    /// As with get_mut(), callers must not change the num_inputs() or num_outputs() of any key.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut K> {
+        self.keys.values_mut()
+    }
+
+    /// Iterate over the keys in this KeySet along with their sizes, as (num_inputs, num_outputs).
+    ///
+    /// The sizes are taken from the keys themselves, not from the sort keys, so they are correct
+    /// regardless of `Order`.
+    pub fn iter_sizes(&self) -> impl Iterator<Item = ((usize, usize), &K)> {
+        self.keys
+            .values()
+            .map(|key| ((key.num_inputs(), key.num_outputs()), key))
+    }
+
+    /// Iterate over the keys in this KeySet along with their sizes, as (num_inputs, num_outputs,
+    /// key), ordered by number of inputs and then number of outputs regardless of `Order`.
+    ///
+    /// This gives KeySets with the same keys the same iteration order even if they use different
+    /// orderings, for example for a canonical export. The keys are collected and sorted up front,
+    /// which takes O(n) space and O(n log n) time, or O(n) time if `Order` is `OrderByInputs`, since
+    /// the sort is then already done.
+    pub fn iter_canonical(&self) -> impl Iterator<Item = (usize, usize, &K)> {
+        let mut keys: Vec<_> = self
+            .iter()
+            .map(|key| (key.num_inputs(), key.num_outputs(), key))
+            .collect();
+        keys.sort_by_key(|&(num_inputs, num_outputs, _)| (num_inputs, num_outputs));
+        keys.into_iter()
+    }
+
+    /// Consume this KeySet, yielding each key along with its size, as (num_inputs, num_outputs).
+    ///
+    /// This is the consuming counterpart of iter_sizes(). Keys are yielded in sort order.
+    pub fn into_pairs(self) -> impl Iterator<Item = ((usize, usize), K)> {
+        self.keys
+            .into_values()
+            .map(|key| ((key.num_inputs(), key.num_outputs()), key))
+    }
+
+    /// The number of keys in this KeySet.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Whether this KeySet contains no keys.
+    ///
+    /// A KeySet built by new() is never empty, but the `Default` KeySet is, and so is one emptied by
+    /// remove(), retain(), clear(), retain_largest_n() or split_at_size(). It is a cheap check to
+    /// make before calling max_size(), which panics on an empty KeySet, although try_max_size()
+    /// avoids the need for it.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Convert this KeySet to a different ordering.
+    ///
+    /// Since each key carries its own size, the index can be rebuilt under `NewOrder` without
+    /// losing information. This never fails as long as `NewOrder` gives distinct sizes distinct
+    /// sort keys, as all the orderings in this crate do.
+    ///
+    /// Conversions between the orderings in this crate are also available through `From`. A
+    /// blanket `impl<O1, O2> From<KeySet<K, O1>> for KeySet<K, O2>` would overlap with the standard
+    /// library's reflexive `impl<T> From<T> for T` when `O1` and `O2` are the same type, so `From`
+    /// is implemented for each pair of distinct built-in orderings instead, and custom orderings
+    /// have to use this method.
+    pub fn reorder<NewOrder: KeyOrder>(self) -> KeySet<K, NewOrder> {
+        KeySet {
+            keys: self
+                .keys
+                .into_values()
+                .map(|key| (NewOrder::sort_key(key.num_inputs(), key.num_outputs()), key))
+                .collect(),
+        }
+    }
+
+    /// Write the canonical serialization of this KeySet to `writer`.
+    ///
+    /// This is the same as `CanonicalSerialize::serialize`, which writes each key to `writer` as it
+    /// goes. To write a very large KeySet without holding its serialization in memory, pass a file
+    /// or other stream rather than a `Vec<u8>`.
+    pub fn serialize_to<W: Write>(&self, writer: W) -> Result<(), SerializationError> {
+        CanonicalSerialize::serialize(self, writer)
+    }
+
+    /// Read a KeySet written by serialize_to() (or `CanonicalSerialize`) from `reader`.
+    ///
+    /// This is the same as `CanonicalDeserialize::deserialize`, which reads one key at a time from
+    /// `reader`.
+    pub fn deserialize_from<R: Read>(reader: R) -> Result<Self, SerializationError> {
+        CanonicalDeserialize::deserialize(reader)
+    }
+
+    /// Write this KeySet to `writer` with compressed curve points.
+    ///
+    /// This refers to ark-serialize's point compression, which is what `CanonicalSerialize`
+    /// uses by default, so the output is the same as that of serialize_to(). Compressed points take
+    /// about half the space of uncompressed ones, but deserializing them requires recovering each
+    /// point's y-coordinate, which is much slower. See serialize_uncompressed_to() for the other
+    /// side of that trade-off.
+    pub fn serialize_compressed_to<W: Write>(&self, writer: W) -> Result<(), SerializationError> {
+        CanonicalSerialize::serialize(self, writer)
+    }
+
+    /// Read a KeySet written by serialize_compressed_to() from `reader`.
+    pub fn deserialize_compressed_from<R: Read>(reader: R) -> Result<Self, SerializationError> {
+        CanonicalDeserialize::deserialize(reader)
+    }
+
+    /// Write this KeySet to `writer` with uncompressed curve points.
+    ///
+    /// The output is larger than that of serialize_compressed_to(), but much faster to
+    /// deserialize, which suits proving keys which are loaded often from fast local storage.
+    pub fn serialize_uncompressed_to<W: Write>(&self, writer: W) -> Result<(), SerializationError> {
+        CanonicalSerialize::serialize_uncompressed(self, writer)
+    }
+
+    /// Read a KeySet written by serialize_uncompressed_to() from `reader`.
+    pub fn deserialize_uncompressed_from<R: Read>(reader: R) -> Result<Self, SerializationError> {
+        CanonicalDeserialize::deserialize_uncompressed(reader)
+    }
+
+    /// The number of bytes written by serialize_uncompressed_to().
+    pub fn uncompressed_size(&self) -> usize {
+        CanonicalSerialize::uncompressed_size(self)
+    }
+
+    /// Deserialize a KeySet from its canonical serialization and validate() it.
+    ///
+    /// This should be used instead of `CanonicalDeserialize` for untrusted bytes, since a KeySet
+    /// whose index disagrees with the sizes of its keys would return the wrong keys from lookups.
+    pub fn from_bytes_checked(bytes: &[u8]) -> Result<Self, Error> {
+        let set: Self = CanonicalDeserialize::deserialize(bytes)
+            .map_err(|source| Error::Deserialization { source })?;
+        set.validate()?;
+        Ok(set)
+    }
+
+    /// Read a KeySet from its canonical serialization in `reader`, refusing to read more than
+    /// `max_keys` keys, and validate() it.
+    ///
+    /// The serialization starts with the number of keys, which an attacker can set arbitrarily, so
+    /// this checks the count against `max_keys` before reading any keys, failing with
+    /// [Error::TooManyKeys] if it is too large. This bounds the number of keys, not the size of
+    /// each one, so it should be combined with a limit on the length of the input.
+    pub fn deserialize_bounded<R: Read>(mut reader: R, max_keys: usize) -> Result<Self, Error> {
+        let num_keys: u64 = CanonicalDeserialize::deserialize(&mut reader)
+            .map_err(|source| Error::Deserialization { source })?;
+        if num_keys > max_keys as u64 {
+            return Err(Error::TooManyKeys { num_keys, max_keys });
+        }
+        let mut keys = BTreeMap::new();
+        for _ in 0..num_keys {
+            let sort_key = CanonicalDeserialize::deserialize(&mut reader)
+                .map_err(|source| Error::Deserialization { source })?;
+            let key = CanonicalDeserialize::deserialize(&mut reader)
+                .map_err(|source| Error::Deserialization { source })?;
+            keys.insert(sort_key, key);
+        }
+        Self::from_inner(keys)
+    }
+
+    /// Build a new KeySet by applying `f` to each key in this one.
+    ///
+    /// This fails if the mapped keys do not have distinct sizes. If `f` preserves the size of each
+    /// key, no duplicates can arise, so this only fails if this KeySet is empty.
+    pub fn map<K2: SizedKey, F: FnMut(&K) -> K2>(&self, f: F) -> Result<KeySet<K2, Order>, Error> {
+        KeySet::new(self.iter().map(f))
+    }
+
+    /// The number of bytes in the canonical serialization of this KeySet.
+    ///
+    /// This is the same as `CanonicalSerialize::serialized_size`, which computes the size without
+    /// serializing anything, and is provided so that it can be called without importing the trait.
+    pub fn serialized_size(&self) -> usize {
+        CanonicalSerialize::serialized_size(self)
+    }
+
+    /// A summary of this KeySet, for diagnostics.
+    pub fn stats(&self) -> KeySetStats {
+        KeySetStats {
+            len: self.len(),
+            min_size: self.try_min_size(),
+            max_size: self.try_max_size(),
+            input_range: self.try_input_range(),
+            output_range: self.try_output_range(),
+            serialized_size: self.serialized_size(),
+        }
+    }
+
+    /// Check that this KeySet satisfies the invariants established by new().
+    ///
+    /// The index of a KeySet and its keys are serialized independently, so a KeySet deserialized
+    /// from an untrusted source could store a key under a sort key that does not match its size.
+    /// This fails with `CorruptIndex` if any key is indexed incorrectly, or `NoKeys` if the KeySet
+    /// is empty.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.is_empty() {
+            return Err(Error::NoKeys);
+        }
+        for (sort_key, key) in &self.keys {
+            if *sort_key != Order::sort_key(key.num_inputs(), key.num_outputs()) {
+                return Err(Error::CorruptIndex {
+                    num_inputs: key.num_inputs(),
+                    num_outputs: key.num_outputs(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Consume this KeySet, returning its index: a map from the sort key of each key to the key.
+    pub fn into_inner(self) -> BTreeMap<Order::SortKey, K> {
+        self.keys
+    }
+
+    /// Create a KeySet from an index like the one returned by into_inner().
+    ///
+    /// The index is checked with validate(), so this fails with `NoKeys` if it is empty, or
+    /// `CorruptIndex` if any key is stored under the wrong sort key.
+    pub fn from_inner(keys: BTreeMap<Order::SortKey, K>) -> Result<Self, Error> {
+        let set = Self { keys };
+        set.validate()?;
+        Ok(set)
+    }
+
+    /// Add all of the keys from `other` to this KeySet.
+    ///
+    /// When both KeySets contain a key of the same size, `on_conflict(existing, incoming)` is
+    /// called to decide which key to keep, for example the newer one. The key it returns is stored
+    /// under the same size, so it must have the same size as its arguments; this is checked in
+    /// debug builds.
+    pub fn merge<F: FnMut(K, K) -> K>(&mut self, other: KeySet<K, Order>, mut on_conflict: F) {
+        for (sort_key, incoming) in other.keys {
+            let key = match self.keys.remove(&sort_key) {
+                Some(existing) => {
+                    let size = (incoming.num_inputs(), incoming.num_outputs());
+                    let key = on_conflict(existing, incoming);
+                    debug_assert_eq!(
+                        (key.num_inputs(), key.num_outputs()),
+                        size,
+                        "merged key does not match the size of the keys it replaces"
+                    );
+                    key
+                }
+                None => incoming,
+            };
+            self.keys.insert(sort_key, key);
+        }
+    }
+
+    /// Add a key to this KeySet, replacing and returning any existing key with the same size.
+    ///
+    /// The key is indexed by its own size, so unlike mutating a key through get_mut(), this cannot
+    /// corrupt the KeySet. Callers must make sure the new key reports the size they intend to
+    /// replace: if it has a different size, it is added alongside the old key and `None` is
+    /// returned.
+    pub fn replace(&mut self, key: K) -> Option<K> {
+        self.keys
+            .insert(Order::sort_key(key.num_inputs(), key.num_outputs()), key)
+    }
+
+    /// Get the entry for the size (num_inputs, num_outputs), for lookup or insertion in place.
+    pub fn entry(&mut self, num_inputs: usize, num_outputs: usize) -> Entry<'_, K, Order> {
+        Entry {
+            num_inputs,
+            num_outputs,
+            entry: self.keys.entry(Order::sort_key(num_inputs, num_outputs)),
+        }
+    }
+
+    /// Remove and return the key whose size is (num_inputs, num_outputs), if there is one.
+    ///
+    /// Removing the last key leaves the KeySet empty, which violates the invariant established
+    /// by new(). Callers must not call max_size() on the KeySet until a key is added back.
+    pub fn remove(&mut self, num_inputs: usize, num_outputs: usize) -> Option<K> {
+        self.keys.remove(&Order::sort_key(num_inputs, num_outputs))
+    }
+
+    /// Remove all keys from this KeySet.
+    ///
+    /// The resulting KeySet is empty, which violates the invariant established by new(), until
+    /// keys are added back with insert().
+    pub fn clear(&mut self) {
+        self.keys.clear()
+    }
+
+    /// Retain only the keys for which `f(num_inputs, num_outputs, key)` returns `true`.
+    ///
+    /// Like remove(), this can leave the KeySet empty, after which max_size() would panic.
+    pub fn retain<F: FnMut(usize, usize, &K) -> bool>(&mut self, mut f: F) {
+        self.keys
+            .retain(|_, key| f(key.num_inputs(), key.num_outputs(), key))
+    }
+
+    /// Remove every key except the largest one, as returned by max_size().
+    ///
+    /// This keeps the ability to prove the largest supported size with as little memory as
+    /// possible. The KeySet remains non-empty as long as it was non-empty to begin with.
+    pub fn prune_to_max_size(&mut self) {
+        let largest = self.keys.pop_last();
+        self.keys.clear();
+        self.keys.extend(largest);
+    }
+
+    /// Keep only the `n` largest keys in sort order, removing the rest.
+    ///
+    /// If `n` is at least the number of keys, nothing is removed. If `n` is 0, the KeySet is left
+    /// empty, like after clear(). Since the keys are already sorted, this only walks past the
+    /// keys being removed.
+    pub fn retain_largest_n(&mut self, n: usize) {
+        let num_removed = self.keys.len().saturating_sub(n);
+        match self.keys.keys().nth(num_removed).cloned() {
+            Some(smallest_kept) => self.keys = self.keys.split_off(&smallest_kept),
+            None => self.keys.clear(),
+        }
+    }
+
+    /// Split this KeySet into the keys whose sort key is less than that of (num_inputs,
+    /// num_outputs), and the keys whose sort key is at least that.
+    ///
+    /// As with lower_bound_key(), the split follows the sort order of `Order`, not the sizes of
+    /// the keys in each dimension. If every key falls on one side of the threshold, the other
+    /// KeySet is empty.
+    pub fn split_at_size(mut self, num_inputs: usize, num_outputs: usize) -> (Self, Self) {
+        let large = self
+            .keys
+            .split_off(&Order::sort_key(num_inputs, num_outputs));
+        (self, Self { keys: large })
+    }
+
+    /// Rebuild the index of this KeySet from scratch.
+    ///
+    /// Removing many keys can leave the nodes of the underlying BTreeMap sparsely filled, and
+    /// BTreeMap has no shrink_to_fit(). Rebuilding packs the remaining keys into as few nodes as
+    /// possible. The keys themselves are moved, not copied, but the cost is still O(n) in the
+    /// number of keys, so this is worth doing after bulk removals, not after every one.
+    pub fn compact(&mut self) {
+        self.keys = core::mem::take(&mut self.keys).into_iter().collect();
+    }
 }
 
-#[derive(Debug, Clone, CanonicalSerialize, CanonicalDeserialize, Serialize, Deserialize)]
-pub struct VerifierKeySet<Order: KeyOrder = OrderByInputs> {
-    // TODO: is there a way to keep these types distinct?
-    pub mint: TransactionVerifyingKey,
-    pub xfr: KeySet<TransactionVerifyingKey, Order>,
-    pub freeze: KeySet<TransactionVerifyingKey, Order>,
+#[cfg(feature = "serde")]
+impl<K: SizedKey + Serialize + for<'a> Deserialize<'a>, Order: KeyOrder> KeySet<K, Order> {
+    /// Serialize this KeySet as JSON.
+    ///
+    /// Each key is keyed by its size, written as a string like `"3-2"` for 3 inputs and 2 outputs
+    /// (see [ShapeKeys]).
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(self)
+    }
+
+    /// Deserialize a KeySet from JSON produced by to_json().
+    pub fn from_json(s: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(s)
+    }
+
+    /// Serialize this KeySet with bincode, using the configuration `options`.
+    ///
+    /// The `Committable` implementations in this crate use the configuration of `bincode::serialize`,
+    /// which is equivalent to
+    /// `bincode::DefaultOptions::new().with_fixint_encoding().allow_trailing_bytes()`
+    /// (fixed-width, little-endian integers). Bytes produced with any other configuration will not
+    /// match those commitments.
+    pub fn to_bincode<O: bincode::Options>(&self, options: O) -> Result<Vec<u8>, bincode::Error> {
+        options.serialize(self)
+    }
+
+    /// Deserialize a KeySet produced by to_bincode() with the same `options`.
+    pub fn from_bincode<O: bincode::Options>(
+        bytes: &[u8],
+        options: O,
+    ) -> Result<Self, bincode::Error> {
+        options.deserialize(bytes)
+    }
 }
 
-impl Committable for VerifierKeySet {
-    fn commit(&self) -> Commitment<Self> {
-        commit::RawCommitmentBuilder::new("VerifCRS Comm")
-            .var_size_bytes(&bincode::serialize(self).unwrap())
-            .finalize()
+/// An empty KeySet.
+///
+/// This is implemented manually, rather than derived, so that it does not require `K: Default`.
+impl<K: SizedKey, Order: KeyOrder> Default for KeySet<K, Order> {
+    fn default() -> Self {
+        Self {
+            keys: BTreeMap::new(),
+        }
+    }
+}
+
+/// Proving keys are large, so the `Debug` representation of a KeySet only lists the ordering and
+/// the sizes of its keys, like `KeySet { order: "by_inputs", shapes: [(1, 2), (2, 2)] }`. Use
+/// KeySet::debug_full() to include the keys themselves.
+impl<K: SizedKey, Order: KeyOrder> Debug for KeySet<K, Order> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeySet")
+            .field("order", &Order::name())
+            .field(
+                "shapes",
+                &self.iter_sizes().map(|(size, _)| size).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+/// The full `Debug` representation of a [KeySet], including its keys, returned by
+/// KeySet::debug_full().
+pub struct DebugFull<'a, K: SizedKey, Order: KeyOrder>(&'a KeySet<K, Order>);
+
+impl<'a, K: SizedKey + Debug, Order: KeyOrder> Debug for DebugFull<'a, K, Order> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeySet")
+            .field("order", &Order::name())
+            .field("keys", &self.0.keys)
+            .finish()
+    }
+}
+
+/// A summary of a [KeySet], returned by KeySet::stats().
+///
+/// Sizes are given as (num_inputs, num_outputs) and ranges as (min, max). Each is `None` if the
+/// KeySet is empty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct KeySetStats {
+    /// The number of keys.
+    pub len: usize,
+    /// The smallest size in sort order, as returned by KeySet::try_min_size().
+    pub min_size: Option<(usize, usize)>,
+    /// The largest size in sort order, as returned by KeySet::try_max_size().
+    pub max_size: Option<(usize, usize)>,
+    /// The smallest and largest number of inputs of any key.
+    pub input_range: Option<(usize, usize)>,
+    /// The smallest and largest number of outputs of any key.
+    pub output_range: Option<(usize, usize)>,
+    /// The number of bytes in the canonical serialization of the KeySet.
+    pub serialized_size: usize,
+}
+
+/// The size of a key, as (num_inputs, num_outputs).
+fn key_size<K: KeyShape>(key: &K) -> (usize, usize) {
+    (key.num_inputs(), key.num_outputs())
+}
+
+/// Scan the entries of `map` which might be large enough for (num_inputs, num_outputs), in sort
+/// order.
+///
+/// This is the search behind best_fit_key() and its variants, for any index keyed by the sort keys
+/// of `Order`, where `size` gets the (num_inputs, num_outputs) of an entry. The scan starts at the
+/// sort key of the requested size, but that only bounds the search: we are not guaranteed that
+/// everything after it has `inputs >= num_inputs` _and_ `outputs >= num_outputs`. For example, if
+/// `Order` is `OrderByInputs`, everything in the range has `inputs >= num_inputs`, but not
+/// necessarily `outputs >= num_outputs`, since, e.g. (3, 1) >= (2, 2) even though 1 < 2. So each
+/// entry in the range is yielded along with its size if it satisfies both constraints, or as
+/// `None` if it doesn't, so that callers can bound the number of entries they examine.
+fn scan_fit<Order: KeyOrder, V>(
+    map: &BTreeMap<Order::SortKey, V>,
+    num_inputs: usize,
+    num_outputs: usize,
+    size: impl Fn(&V) -> (usize, usize),
+) -> impl Iterator<Item = Option<(&Order::SortKey, (usize, usize), &V)>> {
+    map.range((
+        Included(Order::sort_key(num_inputs, num_outputs)),
+        Unbounded,
+    ))
+    .map(move |(sort_key, value)| {
+        let (key_inputs, key_outputs) = size(value);
+        if key_inputs >= num_inputs && key_outputs >= num_outputs {
+            Some((sort_key, (key_inputs, key_outputs), value))
+        } else {
+            None
+        }
+    })
+}
+
+/// The minimum and maximum of a sequence of sizes, or `None` if it is empty.
+fn dimension_range(sizes: impl Iterator<Item = usize>) -> Option<(usize, usize)> {
+    sizes.fold(None, |range, size| match range {
+        Some((min, max)) => Some((min.min(size), max.max(size))),
+        None => Some((size, size)),
+    })
+}
+
+/// Find every size which is shared by more than one of `keys`, in sort order.
+///
+/// KeySet::new() only reports the first duplicate it encounters. This reports all of them, so that
+/// keys assembled from several sources can be fixed in one pass. Sizes are compared by their sort
+/// key under `Order`, just as KeySet::new() would compare them, and each duplicated size is listed
+/// once.
+pub fn find_duplicate_shapes<K: SizedKey, Order: KeyOrder>(keys: &[K]) -> Vec<(usize, usize)> {
+    let mut counts = BTreeMap::<Order::SortKey, ((usize, usize), usize)>::new();
+    for key in keys {
+        let size = (key.num_inputs(), key.num_outputs());
+        counts
+            .entry(Order::sort_key(size.0, size.1))
+            .or_insert((size, 0))
+            .1 += 1;
+    }
+    counts
+        .into_values()
+        .filter(|&(_, count)| count > 1)
+        .map(|(size, _)| size)
+        .collect()
+}
+
+/// Collect keys into a KeySet.
+///
+/// Panics if the iterator is empty or contains two keys with the same size. Use
+/// KeySet::try_from_iter() to handle these cases as errors.
+impl<K: SizedKey, Order: KeyOrder> FromIterator<K> for KeySet<K, Order> {
+    fn from_iter<T: IntoIterator<Item = K>>(iter: T) -> Self {
+        Self::try_from_iter(iter).unwrap()
+    }
+}
+
+/// Implement `From` between KeySets with each pair of distinct orderings, via KeySet::reorder().
+macro_rules! impl_from_reordered {
+    ($($from:ty => $($to:ty),+;)+) => {
+        $($(
+            impl<K: SizedKey> From<KeySet<K, $from>> for KeySet<K, $to> {
+                fn from(set: KeySet<K, $from>) -> Self {
+                    set.reorder()
+                }
+            }
+        )+)+
+    };
+}
+
+impl_from_reordered! {
+    OrderByInputs => OrderByOutputs, OrderBySum, OrderByMax;
+    OrderByOutputs => OrderByInputs, OrderBySum, OrderByMax;
+    OrderBySum => OrderByInputs, OrderByOutputs, OrderByMax;
+    OrderByMax => OrderByInputs, OrderByOutputs, OrderBySum;
+}
+
+/// Convert a vector of keys into a KeySet, failing under the same conditions as KeySet::new().
+impl<K: SizedKey, Order: KeyOrder> TryFrom<Vec<K>> for KeySet<K, Order> {
+    type Error = Error;
+
+    fn try_from(keys: Vec<K>) -> Result<Self, Error> {
+        Self::new(keys.into_iter())
+    }
+}
+
+impl<K: SizedKey, Order: KeyOrder> IntoIterator for KeySet<K, Order> {
+    type Item = K;
+    type IntoIter = btree_map::IntoValues<Order::SortKey, K>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.keys.into_values()
+    }
+}
+
+impl<'a, K: SizedKey, Order: KeyOrder> IntoIterator for &'a KeySet<K, Order> {
+    type Item = &'a K;
+    type IntoIter = btree_map::Values<'a, Order::SortKey, K>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.keys.values()
+    }
+}
+
+/// Hash the keys in a KeySet, along with their sizes, in sort order.
+impl<K: SizedKey + Hash, Order: KeyOrder> Hash for KeySet<K, Order> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.keys.len().hash(state);
+        for ((num_inputs, num_outputs), key) in self.iter_sizes() {
+            num_inputs.hash(state);
+            num_outputs.hash(state);
+            key.hash(state);
+        }
+    }
+}
+
+/// Add the keys from an iterator to a KeySet.
+///
+/// Since `extend` cannot fail, keys whose size is already present in the KeySet are silently
+/// skipped; the first key seen for each size is kept. Use insert() to detect duplicates.
+impl<K: SizedKey, Order: KeyOrder> Extend<K> for KeySet<K, Order> {
+    fn extend<T: IntoIterator<Item = K>>(&mut self, iter: T) {
+        for key in iter {
+            // Ignore DuplicateKeys errors, keeping the existing key.
+            let _ = self.insert(key);
+        }
+    }
+}
+
+/// A single size in a [KeySet], which may or may not have a key. Returned by KeySet::entry().
+#[derive(Debug)]
+pub struct Entry<'a, K: SizedKey, Order: KeyOrder> {
+    num_inputs: usize,
+    num_outputs: usize,
+    entry: btree_map::Entry<'a, Order::SortKey, K>,
+}
+
+impl<'a, K: SizedKey, Order: KeyOrder> Entry<'a, K, Order> {
+    /// The size of this entry, as (num_inputs, num_outputs).
+    pub fn size(&self) -> (usize, usize) {
+        (self.num_inputs, self.num_outputs)
+    }
+
+    /// Whether the KeySet contains a key of this size.
+    pub fn is_occupied(&self) -> bool {
+        matches!(self.entry, btree_map::Entry::Occupied(_))
+    }
+
+    /// Get the key of this size, or insert the key returned by `f` if there isn't one.
+    ///
+    /// The key returned by `f` must have the size of this entry; this is checked in debug builds.
+    /// As with KeySet::get_mut(), callers must not change the size of the returned key.
+    pub fn or_insert_with<F: FnOnce() -> K>(self, f: F) -> &'a mut K {
+        let size = self.size();
+        self.entry.or_insert_with(|| {
+            let key = f();
+            debug_assert_eq!(
+                (key.num_inputs(), key.num_outputs()),
+                size,
+                "inserted key does not match the size of its entry"
+            );
+            key
+        })
+    }
+}
+
+/// Incremental construction of a [KeySet].
+///
+/// Unlike KeySet::new(), which needs all of the keys up front, a builder accepts keys one at a
+/// time, reporting a duplicate size as soon as the offending key is added. The requirement that
+/// the KeySet is non-empty is checked by build().
+#[derive(Debug, Clone)]
+pub struct KeySetBuilder<K: SizedKey, Order: KeyOrder = OrderByInputs> {
+    set: KeySet<K, Order>,
+}
+
+impl<K: SizedKey, Order: KeyOrder> Default for KeySetBuilder<K, Order> {
+    fn default() -> Self {
+        Self {
+            set: KeySet::default(),
+        }
+    }
+}
+
+impl<K: SizedKey, Order: KeyOrder> KeySetBuilder<K, Order> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a key. Fails if a key with the same size has already been added.
+    pub fn add(&mut self, key: K) -> Result<&mut Self, Error> {
+        self.set.insert(key)?;
+        Ok(self)
+    }
+
+    /// Add all of the keys in an iterator, stopping at the first key whose size has already been
+    /// added.
+    pub fn add_all(&mut self, keys: impl IntoIterator<Item = K>) -> Result<&mut Self, Error> {
+        for key in keys {
+            self.add(key)?;
+        }
+        Ok(self)
+    }
+
+    /// Build the KeySet. Fails if no keys have been added.
+    pub fn build(self) -> Result<KeySet<K, Order>, Error> {
+        if self.set.is_empty() {
+            return Err(Error::NoKeys);
+        }
+        Ok(self.set)
+    }
+}
+
+/// A [KeySet] whose keys are reference counted.
+///
+/// Proving keys are immutable once loaded, and large, so copying them whenever a key set is cloned
+/// is wasteful. Clones of a SharedKeySet share the same keys. A SharedKeySet is created with
+/// KeySet::into_shared(), and supports the same lookups as a KeySet, but its keys cannot be
+/// mutated; use try_unwrap() to get a KeySet back.
+pub struct SharedKeySet<K: SizedKey, Order: KeyOrder = OrderByInputs> {
+    keys: BTreeMap<Order::SortKey, Arc<K>>,
+}
+
+/// This is implemented manually, rather than derived, so that it does not require `K: Clone`.
+impl<K: SizedKey, Order: KeyOrder> Clone for SharedKeySet<K, Order> {
+    fn clone(&self) -> Self {
+        Self {
+            keys: self.keys.clone(),
+        }
+    }
+}
+
+/// Like that of [KeySet], the `Debug` representation only lists the sizes of the keys.
+impl<K: SizedKey, Order: KeyOrder> Debug for SharedKeySet<K, Order> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedKeySet")
+            .field("order", &Order::name())
+            .field(
+                "shapes",
+                &self
+                    .iter()
+                    .map(|key| (key.num_inputs(), key.num_outputs()))
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl<K: SizedKey, Order: KeyOrder> SharedKeySet<K, Order> {
+    /// The number of keys in this SharedKeySet.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Whether this SharedKeySet contains no keys.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Get the largest size supported by this SharedKeySet, or `None` if there are no keys.
+    pub fn try_max_size(&self) -> Option<(usize, usize)> {
+        let (_, key) = self.keys.iter().next_back()?;
+        Some((key.num_inputs(), key.num_outputs()))
+    }
+
+    /// Return the key whose size is (num_inputs, num_outputs).
+    pub fn get(&self, num_inputs: usize, num_outputs: usize) -> Option<&K> {
+        self.keys
+            .get(&Order::sort_key(num_inputs, num_outputs))
+            .map(|key| &**key)
+    }
+
+    /// Return the key whose size is (num_inputs, num_outputs). This is the same as get().
+    pub fn exact_fit_key(&self, num_inputs: usize, num_outputs: usize) -> Option<&K> {
+        self.get(num_inputs, num_outputs)
+    }
+
+    /// Return a new reference to the key whose size is (num_inputs, num_outputs), which can
+    /// outlive this SharedKeySet.
+    pub fn get_shared(&self, num_inputs: usize, num_outputs: usize) -> Option<Arc<K>> {
+        self.keys
+            .get(&Order::sort_key(num_inputs, num_outputs))
+            .cloned()
+    }
+
+    /// Return the smallest key whose size is at least (num_inputs, num_outputs), exactly as
+    /// KeySet::best_fit_key() would.
+    pub fn best_fit_key(
+        &self,
+        num_inputs: usize,
+        num_outputs: usize,
+    ) -> Result<(usize, usize, &K), BestFitError> {
+        scan_fit::<Order, _>(&self.keys, num_inputs, num_outputs, |key| key_size(&**key))
+            .flatten()
+            .next()
+            .map(|(_, (key_inputs, key_outputs), key)| (key_inputs, key_outputs, &**key))
+            .ok_or_else(|| BestFitError {
+                requested: (num_inputs, num_outputs),
+                largest_available: self.try_max_size().unwrap_or((0, 0)),
+                budget_exhausted: false,
+            })
+    }
+
+    /// Iterate over the keys in this SharedKeySet, in sort order.
+    pub fn iter(&self) -> impl Iterator<Item = &K> {
+        self.keys.values().map(|key| &**key)
+    }
+
+    /// Convert this SharedKeySet back into a KeySet, if no other SharedKeySet or `Arc` shares any
+    /// of its keys.
+    ///
+    /// Otherwise, the SharedKeySet is returned unchanged, except that keys which were not shared
+    /// are now held in new `Arc`s.
+    pub fn try_unwrap(self) -> Result<KeySet<K, Order>, Self> {
+        let mut unique = BTreeMap::new();
+        let mut shared = BTreeMap::new();
+        for (sort_key, key) in self.keys {
+            match Arc::try_unwrap(key) {
+                Ok(key) => {
+                    unique.insert(sort_key, key);
+                }
+                Err(key) => {
+                    shared.insert(sort_key, key);
+                }
+            }
+        }
+        if shared.is_empty() {
+            Ok(KeySet { keys: unique })
+        } else {
+            shared.extend(
+                unique
+                    .into_iter()
+                    .map(|(sort_key, key)| (sort_key, Arc::new(key))),
+            );
+            Err(Self { keys: shared })
+        }
+    }
+}
+
+impl<K: SizedKey, Order: KeyOrder> From<KeySet<K, Order>> for SharedKeySet<K, Order> {
+    fn from(set: KeySet<K, Order>) -> Self {
+        set.into_shared()
+    }
+}
+
+#[cfg(feature = "std")]
+/// A KeySet whose keys are stored in a file and only deserialized when they are needed.
+///
+/// Proving keys are large, and a process often only uses a few sizes, so loading an entire KeySet
+/// into memory can be wasteful. A LazyKeySet reads the sizes of all the keys in a file eagerly, so
+/// that it can answer queries, but only deserializes a key the first time it is returned by
+/// exact_fit_key() or best_fit_key(). Loaded keys are cached for subsequent queries, and a
+/// LazyKeySet can be shared between threads, which load keys independently.
+///
+/// The canonical serialization of a KeySet does not record where each key ends, so it cannot be
+/// indexed without deserializing every key. Instead, a LazyKeySet reads the indexed format written
+/// by KeySet::serialize_indexed(): a table of the sort key, size and serialized length of each key,
+/// followed by the keys themselves. open() reads a file written by KeySet::serialize_indexed(). The
+/// transfer or freeze keys of a [ProverKeySet] can be read from a file written by
+/// ProverKeySet::serialize_indexed() with open_prover_transfer() or open_prover_freeze().
+#[derive(Debug)]
+pub struct LazyKeySet<K: SizedKey, Order: KeyOrder = OrderByInputs> {
+    path: PathBuf,
+    keys: BTreeMap<Order::SortKey, LazyKey<K>>,
+}
+
+#[cfg(feature = "std")]
+#[derive(Debug)]
+struct LazyKey<K> {
+    num_inputs: usize,
+    num_outputs: usize,
+    // The position of the serialized key in the file.
+    offset: u64,
+    key: OnceLock<K>,
+}
+
+#[cfg(feature = "std")]
+impl<K: SizedKey, Order: KeyOrder> KeySet<K, Order> {
+    /// Write this KeySet to `writer` in the indexed format read by LazyKeySet::open().
+    ///
+    /// The output starts with the number of keys and then, for each key in sort order, its sort
+    /// key, its number of inputs and outputs, and the length of its canonical serialization. The
+    /// canonical serializations of the keys follow, in the same order. This is not the canonical
+    /// serialization of the KeySet, and cannot be read with `CanonicalDeserialize`.
+    pub fn serialize_indexed<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        CanonicalSerialize::serialize(&(self.keys.len() as u64), &mut writer)?;
+        for (sort_key, key) in &self.keys {
+            CanonicalSerialize::serialize(sort_key, &mut writer)?;
+            CanonicalSerialize::serialize(&key_size(key), &mut writer)?;
+            CanonicalSerialize::serialize(&(key.serialized_size() as u64), &mut writer)?;
+        }
+        for key in self.keys.values() {
+            CanonicalSerialize::serialize(key, &mut writer)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: SizedKey, Order: KeyOrder> LazyKeySet<K, Order> {
+    /// Index the KeySet stored in the file at `path`.
+    ///
+    /// The file must be written by KeySet::serialize_indexed() with the same `Order`. Only the
+    /// table at the start of the file is read; none of the keys are deserialized. Fails with
+    /// [Error::CorruptIndex] if the table lists a key under the wrong sort key, or [Error::NoKeys]
+    /// if it lists no keys.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::open_after(path, |_| Ok(()))
+    }
+
+    /// Index a KeySet stored in the file at `path` after whatever is read by `skip`.
+    fn open_after(
+        path: impl AsRef<Path>,
+        skip: impl FnOnce(&mut PositionReader<BufReader<File>>) -> Result<(), SerializationError>,
+    ) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let file =
+            File::open(&path).map_err(|err| Error::Deserialization { source: err.into() })?;
+        let mut reader = PositionReader {
+            inner: BufReader::new(file),
+            pos: 0,
+        };
+        skip(&mut reader).map_err(|source| Error::Deserialization { source })?;
+        let table = read_index_table::<Order::SortKey, _>(&mut reader)
+            .map_err(|source| Error::Deserialization { source })?;
+        // The keys start right after the table, in the same order.
+        let mut offset = reader.pos;
+        let mut keys = BTreeMap::new();
+        for (sort_key, (num_inputs, num_outputs), len) in table {
+            if sort_key != Order::sort_key(num_inputs, num_outputs) {
+                return Err(Error::CorruptIndex {
+                    num_inputs,
+                    num_outputs,
+                });
+            }
+            keys.insert(
+                sort_key,
+                LazyKey {
+                    num_inputs,
+                    num_outputs,
+                    offset,
+                    key: OnceLock::new(),
+                },
+            );
+            offset = offset.checked_add(len).ok_or(Error::Deserialization {
+                source: SerializationError::InvalidData,
+            })?;
+        }
+        if keys.is_empty() {
+            return Err(Error::NoKeys);
+        }
+        Ok(Self { path, keys })
+    }
+
+    /// The number of keys in this LazyKeySet, whether or not they have been loaded.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Whether this LazyKeySet contains no keys. This is never true for a LazyKeySet returned by
+    /// open().
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// The number of keys which have been loaded from the file so far.
+    pub fn num_loaded(&self) -> usize {
+        self.keys
+            .values()
+            .filter(|entry| entry.key.get().is_some())
+            .count()
+    }
+
+    /// Get the largest size supported by this LazyKeySet, without loading any keys, or `None` if
+    /// there are no keys.
+    pub fn max_size(&self) -> Option<(usize, usize)> {
+        let (_, entry) = self.keys.iter().next_back()?;
+        Some((entry.num_inputs, entry.num_outputs))
+    }
+
+    /// Iterate over the sizes of the keys in this LazyKeySet, in sort order, without loading any
+    /// keys.
+    pub fn sizes(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.keys
+            .values()
+            .map(|entry| (entry.num_inputs, entry.num_outputs))
+    }
+
+    /// Return the key whose size is (num_inputs, num_outputs), loading it if necessary.
+    pub fn exact_fit_key(
+        &self,
+        num_inputs: usize,
+        num_outputs: usize,
+    ) -> Result<Option<&K>, Error> {
+        match self.keys.get(&Order::sort_key(num_inputs, num_outputs)) {
+            Some(entry) => self.load(entry).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Return the smallest key whose size is at least (num_inputs, num_outputs), loading it if
+    /// necessary. Returns `Ok(None)` if no key is large enough.
+    ///
+    /// Only the returned key is loaded: the search itself uses the eagerly loaded sizes.
+    pub fn best_fit_key(
+        &self,
+        num_inputs: usize,
+        num_outputs: usize,
+    ) -> Result<Option<(usize, usize, &K)>, Error> {
+        let entry = scan_fit::<Order, _>(&self.keys, num_inputs, num_outputs, |entry| {
+            (entry.num_inputs, entry.num_outputs)
+        })
+        .flatten()
+        .next();
+        match entry {
+            Some((_, (key_inputs, key_outputs), entry)) => {
+                Ok(Some((key_inputs, key_outputs, self.load(entry)?)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Load the key for `entry`, or return it if it is already loaded.
+    ///
+    /// Fails with [Error::CorruptIndex] if the key in the file does not have the size listed for it
+    /// in the table.
+    fn load<'s>(&'s self, entry: &'s LazyKey<K>) -> Result<&'s K, Error> {
+        if let Some(key) = entry.key.get() {
+            return Ok(key);
+        }
+        let mut file =
+            File::open(&self.path).map_err(|err| Error::Deserialization { source: err.into() })?;
+        file.seek(SeekFrom::Start(entry.offset))
+            .map_err(|err| Error::Deserialization { source: err.into() })?;
+        let key: K = CanonicalDeserialize::deserialize(BufReader::new(file))
+            .map_err(|source| Error::Deserialization { source })?;
+        if key_size(&key) != (entry.num_inputs, entry.num_outputs) {
+            return Err(Error::CorruptIndex {
+                num_inputs: key.num_inputs(),
+                num_outputs: key.num_outputs(),
+            });
+        }
+        Ok(entry.key.get_or_init(|| key))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a, Order: KeyOrder> ProverKeySet<'a, Order> {
+    /// Write this ProverKeySet to `writer` in the indexed format read by
+    /// LazyKeySet::open_prover_transfer() and LazyKeySet::open_prover_freeze().
+    ///
+    /// The output is the length of the canonical serialization of the mint key, followed by that
+    /// serialization, and then the transfer and freeze keys, each written as by
+    /// KeySet::serialize_indexed().
+    pub fn serialize_indexed<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        CanonicalSerialize::serialize(&(self.mint.serialized_size() as u64), &mut writer)?;
+        CanonicalSerialize::serialize(&self.mint, &mut writer)?;
+        self.xfr.serialize_indexed(&mut writer)?;
+        self.freeze.serialize_indexed(writer)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a, Order: KeyOrder> LazyKeySet<TransferProvingKey<'a>, Order> {
+    /// Index the transfer keys of the ProverKeySet stored in the file at `path`.
+    ///
+    /// The file must be written by ProverKeySet::serialize_indexed() with the same `Order`. The
+    /// mint key, which comes before the transfer keys, is skipped without being read.
+    pub fn open_prover_transfer(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::open_after(path, skip_mint_key)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a, Order: KeyOrder> LazyKeySet<FreezeProvingKey<'a>, Order> {
+    /// Index the freeze keys of the ProverKeySet stored in the file at `path`.
+    ///
+    /// The file must be written by ProverKeySet::serialize_indexed() with the same `Order`. The
+    /// mint key and the transfer keys, which come before the freeze keys, are skipped without being
+    /// read, using the table of the transfer keys to find where they end.
+    pub fn open_prover_freeze(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::open_after(path, |reader| {
+            skip_mint_key(&mut *reader)?;
+            let table = read_index_table::<Order::SortKey, _>(&mut *reader)?;
+            let len = table
+                .iter()
+                .try_fold(0u64, |total, &(_, _, len)| total.checked_add(len))
+                .ok_or(SerializationError::InvalidData)?;
+            reader.skip(len)
+        })
+    }
+}
+
+/// The table at the start of a KeySet written by KeySet::serialize_indexed(): the sort key, size
+/// and serialized length of each key.
+#[cfg(feature = "std")]
+type IndexTable<S> = Vec<(S, (usize, usize), u64)>;
+
+/// Read the table at the start of a KeySet written by KeySet::serialize_indexed().
+#[cfg(feature = "std")]
+fn read_index_table<S: CanonicalDeserialize, R: Read>(
+    mut reader: R,
+) -> Result<IndexTable<S>, SerializationError> {
+    let len: u64 = CanonicalDeserialize::deserialize(&mut reader)?;
+    // Don't trust `len` to preallocate, since the table may be corrupt.
+    let mut table = Vec::new();
+    for _ in 0..len {
+        table.push(CanonicalDeserialize::deserialize(&mut reader)?);
+    }
+    Ok(table)
+}
+
+/// Skip past the mint key at the start of a ProverKeySet written by
+/// ProverKeySet::serialize_indexed().
+#[cfg(feature = "std")]
+fn skip_mint_key(reader: &mut PositionReader<BufReader<File>>) -> Result<(), SerializationError> {
+    let len: u64 = CanonicalDeserialize::deserialize(&mut *reader)?;
+    reader.skip(len)
+}
+
+#[cfg(feature = "std")]
+/// A reader which keeps track of how many bytes have been read from it.
+struct PositionReader<R> {
+    inner: R,
+    pos: u64,
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> Read for PositionReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(feature = "std")]
+impl PositionReader<BufReader<File>> {
+    /// Skip the next `len` bytes without reading them.
+    fn skip(&mut self, len: u64) -> Result<(), SerializationError> {
+        let pos = self
+            .pos
+            .checked_add(len)
+            .ok_or(SerializationError::InvalidData)?;
+        self.inner.seek(SeekFrom::Start(pos))?;
+        self.pos = pos;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, CanonicalSerialize, CanonicalDeserialize, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ProverKeySet<'a, Order: KeyOrder = OrderByInputs> {
+    pub mint: MintProvingKey<'a>,
+    pub xfr: KeySet<TransferProvingKey<'a>, Order>,
+    pub freeze: KeySet<FreezeProvingKey<'a>, Order>,
+}
+
+/// A reference to any of the proving keys in a [ProverKeySet], as yielded by
+/// ProverKeySet::iter_all_keys().
+///
+/// `'r` is the lifetime of the borrow of the ProverKeySet, and `'a` is the lifetime parameter of
+/// the keys themselves.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy)]
+pub enum ProverKeyRef<'r, 'a> {
+    Mint(&'r MintProvingKey<'a>),
+    Transfer(&'r TransferProvingKey<'a>),
+    Freeze(&'r FreezeProvingKey<'a>),
+}
+
+#[cfg(feature = "std")]
+impl<'r, 'a> ProverKeyRef<'r, 'a> {
+    /// The size of the referenced key, as (num_inputs, num_outputs).
+    pub fn shape(&self) -> (usize, usize) {
+        match self {
+            Self::Mint(_) => mint_shape(),
+            Self::Transfer(key) => (key.num_inputs(), key.num_outputs()),
+            Self::Freeze(key) => (key.num_inputs(), key.num_outputs()),
+        }
+    }
+}
+
+/// Validated construction of a [ProverKeySet].
+///
+/// build() checks that a mint key has been given and that the transfer and freeze keys each form
+/// a valid KeySet: non-empty, with no two keys of the same size. On failure, the error is an
+/// [Error::InvalidComponent] naming the component ("mint", "transfer" or "freeze") which is
+/// invalid.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct ProverKeySetBuilder<'a, Order: KeyOrder = OrderByInputs> {
+    mint: Option<MintProvingKey<'a>>,
+    xfr: Vec<TransferProvingKey<'a>>,
+    freeze: Vec<FreezeProvingKey<'a>>,
+    _order: PhantomData<Order>,
+}
+
+#[cfg(feature = "std")]
+impl<'a, Order: KeyOrder> Default for ProverKeySetBuilder<'a, Order> {
+    fn default() -> Self {
+        Self {
+            mint: None,
+            xfr: Vec::new(),
+            freeze: Vec::new(),
+            _order: PhantomData,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a, Order: KeyOrder> ProverKeySetBuilder<'a, Order> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the mint key, replacing any mint key given previously.
+    pub fn mint(&mut self, key: MintProvingKey<'a>) -> &mut Self {
+        self.mint = Some(key);
+        self
+    }
+
+    /// Add transfer keys.
+    pub fn transfer_keys(
+        &mut self,
+        keys: impl IntoIterator<Item = TransferProvingKey<'a>>,
+    ) -> &mut Self {
+        self.xfr.extend(keys);
+        self
+    }
+
+    /// Add freeze keys.
+    pub fn freeze_keys(
+        &mut self,
+        keys: impl IntoIterator<Item = FreezeProvingKey<'a>>,
+    ) -> &mut Self {
+        self.freeze.extend(keys);
+        self
+    }
+
+    /// Build the ProverKeySet, validating all three components.
+    pub fn build(self) -> Result<ProverKeySet<'a, Order>, Error> {
+        let mint = self
+            .mint
+            .ok_or(Error::NoKeys)
+            .map_err(invalid_component("mint"))?;
+        let xfr = KeySet::new(self.xfr.into_iter()).map_err(invalid_component("transfer"))?;
+        let freeze = KeySet::new(self.freeze.into_iter()).map_err(invalid_component("freeze"))?;
+        Ok(ProverKeySet { mint, xfr, freeze })
+    }
+}
+
+/// Wrap an error in [Error::InvalidComponent] for `component`.
+#[cfg(feature = "std")]
+fn invalid_component(component: &'static str) -> impl Fn(Error) -> Error {
+    move |err| Error::InvalidComponent {
+        component,
+        source: Box::new(err),
+    }
+}
+
+#[cfg(feature = "std")]
+/// The verifying keys corresponding to a [ProverKeySet].
+///
+/// There is no `VerifierKeySet::from_prover()`, because jf_cap (as of the `testnet-v1` branch)
+/// offers no way to get a verifying key from a proving key. `TransferProvingKey`,
+/// `MintProvingKey` and `FreezeProvingKey` keep the underlying PLONK proving key, which holds the
+/// verifying key, in a `pub(crate)` field, with public accessors only for their number of inputs
+/// and outputs. The `*VerifyingKey` types likewise have only `pub(crate)` fields, so the only way
+/// to construct one outside jf_cap is to deserialize it or get it from `preprocess`. Instead,
+/// both key sets should be built from the outputs of the same jf_cap `preprocess` calls, each of
+/// which returns a proving key together with its verifying key, using
+/// ProverKeySet::from_preprocessed().
+#[derive(Debug, Clone, CanonicalSerialize, CanonicalDeserialize, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct VerifierKeySet<Order: KeyOrder = OrderByInputs> {
+    // TODO: is there a way to keep these types distinct?
+    pub mint: TransactionVerifyingKey,
+    pub xfr: KeySet<TransactionVerifyingKey, Order>,
+    pub freeze: KeySet<TransactionVerifyingKey, Order>,
+}
+
+#[cfg(feature = "std")]
+impl<'a, Order: KeyOrder> ProverKeySet<'a, Order> {
+    /// Build a ProverKeySet and the matching VerifierKeySet from the output of jf_cap's
+    /// `preprocess` functions, as pairs of a proving key and its verifying key.
+    ///
+    /// Since each verifying key is paired with its proving key, the two key sets have the same
+    /// sizes by construction. This takes the place of deriving a VerifierKeySet from a
+    /// ProverKeySet, which jf_cap's API does not allow (see [VerifierKeySet]). Fails with
+    /// [Error::InvalidComponent] under the same conditions as ProverKeySetBuilder::build().
+    pub fn from_preprocessed(
+        mint: (MintProvingKey<'a>, MintVerifyingKey),
+        xfr: impl IntoIterator<Item = (TransferProvingKey<'a>, TransferVerifyingKey)>,
+        freeze: impl IntoIterator<Item = (FreezeProvingKey<'a>, FreezeVerifyingKey)>,
+    ) -> Result<(Self, VerifierKeySet<Order>), Error> {
+        let (xfr_proving, xfr_verifying): (Vec<_>, Vec<_>) = xfr.into_iter().unzip();
+        let (freeze_proving, freeze_verifying): (Vec<_>, Vec<_>) = freeze.into_iter().unzip();
+        let mut builder = ProverKeySetBuilder::new();
+        builder
+            .mint(mint.0)
+            .transfer_keys(xfr_proving)
+            .freeze_keys(freeze_proving);
+        let prover = builder.build()?;
+        let verifier = VerifierKeySet {
+            mint: TransactionVerifyingKey::Mint(mint.1),
+            xfr: KeySet::new(
+                xfr_verifying
+                    .into_iter()
+                    .map(TransactionVerifyingKey::Transfer),
+            )
+            .map_err(invalid_component("transfer"))?,
+            freeze: KeySet::new(
+                freeze_verifying
+                    .into_iter()
+                    .map(TransactionVerifyingKey::Freeze),
+            )
+            .map_err(invalid_component("freeze"))?,
+        };
+        Ok((prover, verifier))
+    }
+
+    /// The number of bytes in the canonical serialization of this ProverKeySet.
+    pub fn serialized_size(&self) -> usize {
+        CanonicalSerialize::serialized_size(self)
+    }
+
+    /// The number of keys in this ProverKeySet: the mint key, plus every transfer and freeze key.
+    pub fn total_keys(&self) -> usize {
+        1 + self.xfr.len() + self.freeze.len()
+    }
+
+    /// A rough estimate of the memory used by this ProverKeySet, in bytes.
+    ///
+    /// Proving keys are dominated by large vectors of curve points, whose in-memory size is
+    /// proportional to their serialized size, so this uses serialized_size() as a proxy. It does
+    /// not account for point compression, allocator overhead or the BTreeMap nodes, and is meant
+    /// for capacity planning, not exact heap accounting.
+    pub fn memory_footprint(&self) -> usize {
+        self.serialized_size()
+    }
+
+    /// Convert the transfer and freeze KeySets to a different ordering, as with KeySet::reorder().
+    pub fn reorder<NewOrder: KeyOrder>(self) -> ProverKeySet<'a, NewOrder> {
+        ProverKeySet {
+            mint: self.mint,
+            xfr: self.xfr.reorder(),
+            freeze: self.freeze.reorder(),
+        }
+    }
+
+    /// Iterate over every proving key in this ProverKeySet: the mint key, then the transfer keys
+    /// and the freeze keys, each in sort order.
+    pub fn iter_all_keys(&self) -> impl Iterator<Item = ProverKeyRef<'_, 'a>> {
+        core::iter::once(ProverKeyRef::Mint(&self.mint))
+            .chain(self.xfr.iter().map(ProverKeyRef::Transfer))
+            .chain(self.freeze.iter().map(ProverKeyRef::Freeze))
+    }
+
+    /// Check that `verifier` contains the verifying keys corresponding to this ProverKeySet.
+    ///
+    /// Only the sizes of the keys are compared: the mint keys must have the same size, and the
+    /// transfer and freeze KeySets must contain exactly the same sizes. If not, the error lists
+    /// the mismatched sizes of the first component which differs.
+    pub fn verify_against<O2: KeyOrder>(&self, verifier: &VerifierKeySet<O2>) -> Result<(), Error> {
+        let prover = self.shapes();
+        let verifier = verifier.shapes();
+        compare_shapes("mint", &[prover.mint], &[verifier.mint])?;
+        compare_shapes("transfer", &prover.xfr, &verifier.xfr)?;
+        compare_shapes("freeze", &prover.freeze, &verifier.freeze)
+    }
+
+    /// Remove every transfer and freeze key whose size has no verifying key in `verifier`.
+    ///
+    /// This trims an over-provisioned ProverKeySet down to the sizes a verifier actually supports.
+    /// Fails with [Error::NoKeys], leaving this ProverKeySet unchanged, if no transfer keys or no
+    /// freeze keys would remain.
+    pub fn strip_to_verifier_shapes<O2: KeyOrder>(
+        &mut self,
+        verifier: &VerifierKeySet<O2>,
+    ) -> Result<(), Error> {
+        if !self.xfr.any_shape(|num_inputs, num_outputs| {
+            verifier.xfr.contains_size(num_inputs, num_outputs)
+        }) || !self.freeze.any_shape(|num_inputs, num_outputs| {
+            verifier.freeze.contains_size(num_inputs, num_outputs)
+        }) {
+            return Err(Error::NoKeys);
+        }
+        self.xfr.retain(|num_inputs, num_outputs, _| {
+            verifier.xfr.contains_size(num_inputs, num_outputs)
+        });
+        self.freeze.retain(|num_inputs, num_outputs, _| {
+            verifier.freeze.contains_size(num_inputs, num_outputs)
+        });
+        Ok(())
+    }
+
+    /// The sizes of the keys in this ProverKeySet.
+    pub fn shapes(&self) -> KeySetShapes {
+        KeySetShapes {
+            mint: mint_shape(),
+            xfr: self.xfr.iter_sizes().map(|(size, _)| size).collect(),
+            freeze: self.freeze.iter_sizes().map(|(size, _)| size).collect(),
+        }
+    }
+
+    /// Write this ProverKeySet to `writer` in a [KeySetEnvelope] tagged with [CURRENT_VERSION].
+    pub fn serialize_versioned<W: Write>(&self, writer: W) -> Result<(), SerializationError> {
+        serialize_versioned(self, writer)
+    }
+
+    /// Read a ProverKeySet written by serialize_versioned() from `reader`.
+    ///
+    /// Fails with [Error::UnsupportedVersion] if the envelope was written with a different format
+    /// version.
+    pub fn deserialize_versioned<R: Read>(reader: R) -> Result<Self, Error> {
+        Ok(KeySetEnvelope::deserialize_versioned(reader)?.payload)
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<'a, Order: KeyOrder> ProverKeySet<'a, Order>
+where
+    Order::SortKey: Send,
+{
+    /// Deserialize a ProverKeySet from its uncompressed serialization using multiple threads.
+    ///
+    /// This only accepts the uncompressed encoding written by
+    /// `CanonicalSerialize::serialize_uncompressed`, not the default compressed one written by
+    /// `CanonicalSerialize::serialize`. It first scans `bytes` without validation to find the
+    /// boundaries of the mint key and of each transfer and freeze key, and then deserializes the
+    /// keys, with validation, on the rayon thread pool. The result is the same as deserializing
+    /// `bytes` sequentially with `CanonicalDeserialize::deserialize_uncompressed`.
+    ///
+    /// The scan relies on ark-serialize's unchecked reads, which only understand the uncompressed
+    /// encoding. The boundaries of compressed points cannot be found without decompressing them,
+    /// which is the work that would be split between threads, so there is no compressed variant.
+    pub fn from_uncompressed_bytes_parallel(bytes: &[u8]) -> Result<Self, SerializationError> {
+        let mut reader = bytes;
+        let mint = split_entry::<MintProvingKey>(&mut reader)?;
+        let xfr = split_map_entries::<Order::SortKey, TransferProvingKey>(&mut reader)?;
+        let freeze = split_map_entries::<Order::SortKey, FreezeProvingKey>(&mut reader)?;
+
+        let (mint, (xfr, freeze)) = rayon::join(
+            || CanonicalDeserialize::deserialize_uncompressed(mint),
+            || {
+                rayon::join(
+                    || deserialize_map_entries(&xfr),
+                    || deserialize_map_entries(&freeze),
+                )
+            },
+        );
+        Ok(Self {
+            mint: mint?,
+            xfr: KeySet { keys: xfr? },
+            freeze: KeySet { keys: freeze? },
+        })
+    }
+}
+
+/// Skip over the uncompressed serialization of a `T` at the start of `reader`, without validating
+/// it, and return the bytes it occupies.
+#[cfg(feature = "rayon")]
+fn split_entry<'b, T: CanonicalDeserialize>(
+    reader: &mut &'b [u8],
+) -> Result<&'b [u8], SerializationError> {
+    let start = *reader;
+    T::deserialize_unchecked(&mut *reader)?;
+    Ok(&start[..start.len() - reader.len()])
+}
+
+/// Split the uncompressed serialization of a `BTreeMap<S, K>` at the start of `reader` into the
+/// bytes of each of its entries.
+#[cfg(feature = "rayon")]
+fn split_map_entries<'b, S: CanonicalDeserialize, K: CanonicalDeserialize>(
+    reader: &mut &'b [u8],
+) -> Result<Vec<&'b [u8]>, SerializationError> {
+    let len: u64 = CanonicalDeserialize::deserialize(&mut *reader)?;
+    (0..len).map(|_| split_entry::<(S, K)>(reader)).collect()
+}
+
+/// Deserialize the entries split out by split_map_entries() in parallel.
+#[cfg(feature = "rayon")]
+fn deserialize_map_entries<S, K>(entries: &[&[u8]]) -> Result<BTreeMap<S, K>, SerializationError>
+where
+    S: Ord + CanonicalDeserialize + Send,
+    K: CanonicalDeserialize + Send,
+{
+    use rayon::prelude::*;
+
+    let entries = entries
+        .par_iter()
+        .map(|entry| CanonicalDeserialize::deserialize_uncompressed(*entry))
+        .collect::<Result<Vec<(S, K)>, _>>()?;
+    // Insert the entries in their serialized order, as sequential deserialization would.
+    Ok(entries.into_iter().collect())
+}
+
+#[cfg(feature = "std")]
+fn compare_shapes(
+    component: &'static str,
+    prover: &[(usize, usize)],
+    verifier: &[(usize, usize)],
+) -> Result<(), Error> {
+    let missing_from_verifier: Vec<_> = prover
+        .iter()
+        .filter(|size| !verifier.contains(size))
+        .cloned()
+        .collect();
+    let missing_from_prover: Vec<_> = verifier
+        .iter()
+        .filter(|size| !prover.contains(size))
+        .cloned()
+        .collect();
+    if missing_from_verifier.is_empty() && missing_from_prover.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::KeySetMismatch {
+            component,
+            missing_from_verifier,
+            missing_from_prover,
+        })
+    }
+}
+
+/// A compact description of the sizes supported by a [ProverKeySet] or [VerifierKeySet].
+///
+/// This can be exchanged with peers to check compatibility without transmitting the keys
+/// themselves. Each size is given as (num_inputs, num_outputs), and the transfer and freeze sizes
+/// are listed in the sort order of their KeySets.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct KeySetShapes {
+    pub mint: (usize, usize),
+    pub xfr: Vec<(usize, usize)>,
+    pub freeze: Vec<(usize, usize)>,
+}
+
+#[cfg(feature = "compression")]
+impl<'a, Order: KeyOrder> ProverKeySet<'a, Order> {
+    /// Write the canonical serialization of this ProverKeySet to `writer`, compressed with gzip.
+    ///
+    /// Despite the name, this is unrelated to the point compression of
+    /// KeySet::serialize_compressed_to(): the keys are serialized as usual, with compressed points,
+    /// and the resulting bytes are gzipped.
+    ///
+    /// Proving keys are large, and compress well, so this is useful for storing them on disk. The
+    /// gzip header identifies the format, so deserialize_compressed() fails cleanly on input which
+    /// was not written by this function.
+    pub fn serialize_compressed<W: Write>(&self, writer: W) -> Result<(), SerializationError> {
+        let mut encoder = GzEncoder::new(writer, Compression::default());
+        CanonicalSerialize::serialize(self, &mut encoder)?;
+        encoder.finish()?;
+        Ok(())
+    }
+
+    /// Read a ProverKeySet written by serialize_compressed() from `reader`.
+    pub fn deserialize_compressed<R: Read>(reader: R) -> Result<Self, SerializationError> {
+        CanonicalDeserialize::deserialize(GzDecoder::new(reader))
+    }
+}
+
+#[cfg(feature = "checksum")]
+impl<'a, Order: KeyOrder> ProverKeySet<'a, Order> {
+    /// Write the canonical serialization of this ProverKeySet to `writer`, followed by its SHA-256
+    /// digest.
+    ///
+    /// This protects stored keys against corruption. Unlike a commitment, the digest is not meant
+    /// to identify the key set, only to check that it was read back intact.
+    pub fn serialize_with_checksum<W: Write>(&self, writer: W) -> Result<(), SerializationError> {
+        serialize_with_checksum(self, writer)
+    }
+
+    /// Read a ProverKeySet written by serialize_with_checksum() from `reader`.
+    ///
+    /// The payload and digest must be all that is left in `reader`, which is read to the end.
+    /// Fails with [Error::ChecksumMismatch] if the digest does not match the payload.
+    ///
+    /// The digest is checked before the keys are deserialized, so corrupt keys are never mistaken
+    /// for invalid ones. To do so, the serialized payload is held in memory until the keys have
+    /// been deserialized from it.
+    pub fn deserialize_with_checksum<R: Read>(reader: R) -> Result<Self, Error> {
+        deserialize_with_checksum(reader)
+    }
+}
+
+#[cfg(feature = "checksum")]
+impl<Order: KeyOrder> VerifierKeySet<Order> {
+    /// Write the canonical serialization of this VerifierKeySet to `writer`, followed by its
+    /// SHA-256 digest.
+    ///
+    /// See [ProverKeySet::serialize_with_checksum].
+    pub fn serialize_with_checksum<W: Write>(&self, writer: W) -> Result<(), SerializationError> {
+        serialize_with_checksum(self, writer)
+    }
+
+    /// Read a VerifierKeySet written by serialize_with_checksum() from `reader`.
+    ///
+    /// Fails with [Error::ChecksumMismatch] if the digest does not match the payload. See
+    /// [ProverKeySet::deserialize_with_checksum].
+    pub fn deserialize_with_checksum<R: Read>(reader: R) -> Result<Self, Error> {
+        deserialize_with_checksum(reader)
+    }
+}
+
+#[cfg(feature = "checksum")]
+fn serialize_with_checksum<T: CanonicalSerialize, W: Write>(
+    payload: &T,
+    writer: W,
+) -> Result<(), SerializationError> {
+    // Hash the payload as it is written, rather than buffering it, since proving keys are large.
+    let mut writer = HashingWriter {
+        inner: writer,
+        hasher: Sha256::new(),
+    };
+    CanonicalSerialize::serialize(payload, &mut writer)?;
+    let digest = writer.hasher.finalize();
+    writer.inner.write_all(digest.as_slice())?;
+    Ok(())
+}
+
+#[cfg(feature = "checksum")]
+fn deserialize_with_checksum<T: CanonicalDeserialize, R: Read>(mut reader: R) -> Result<T, Error> {
+    // The digest has to be checked before the payload is deserialized, which is slow and would
+    // report corrupt keys as invalid rather than corrupt. The end of the payload cannot be found
+    // without deserializing it, so the digest is taken to be the last 32 bytes of `reader`.
+    let mut bytes = Vec::new();
+    reader
+        .read_to_end(&mut bytes)
+        .map_err(|err| Error::Deserialization { source: err.into() })?;
+    if bytes.len() < 32 {
+        return Err(Error::Deserialization {
+            source: std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into(),
+        });
+    }
+    let (payload, digest) = bytes.split_at(bytes.len() - 32);
+    if Sha256::digest(payload).as_slice() != digest {
+        return Err(Error::ChecksumMismatch);
+    }
+    CanonicalDeserialize::deserialize(payload).map_err(|source| Error::Deserialization { source })
+}
+
+/// A writer which hashes everything written through it.
+#[cfg(feature = "checksum")]
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+#[cfg(feature = "checksum")]
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(feature = "std")]
+// Equality of verifying keys is reflexive, so VerifierKeySet can be `Eq` (and thus used as a
+// `HashMap` key) without requiring `TransactionVerifyingKey: Eq`.
+impl<Order: KeyOrder + PartialEq> Eq for VerifierKeySet<Order> {}
+
+#[cfg(feature = "std")]
+/// `TransactionVerifyingKey` does not implement `Hash`, so a VerifierKeySet is hashed via its
+/// canonical serialization.
+impl<Order: KeyOrder> Hash for VerifierKeySet<Order> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut bytes = Vec::new();
+        CanonicalSerialize::serialize(self, &mut bytes).unwrap();
+        bytes.hash(state);
+    }
+}
+
+#[cfg(feature = "std")]
+impl<Order: KeyOrder> VerifierKeySet<Order> {
+    /// The number of bytes in the canonical serialization of this VerifierKeySet.
+    pub fn serialized_size(&self) -> usize {
+        CanonicalSerialize::serialized_size(self)
+    }
+
+    /// The number of keys in this VerifierKeySet: the mint key, plus every transfer and freeze key.
+    pub fn total_keys(&self) -> usize {
+        1 + self.xfr.len() + self.freeze.len()
+    }
+
+    /// Convert the transfer and freeze KeySets to a different ordering, as with KeySet::reorder().
+    pub fn reorder<NewOrder: KeyOrder>(self) -> VerifierKeySet<NewOrder> {
+        VerifierKeySet {
+            mint: self.mint,
+            xfr: self.xfr.reorder(),
+            freeze: self.freeze.reorder(),
+        }
+    }
+
+    /// The sizes of the keys in this VerifierKeySet.
+    pub fn shapes(&self) -> KeySetShapes {
+        KeySetShapes {
+            mint: (self.mint.num_inputs(), self.mint.num_outputs()),
+            xfr: self.xfr.iter_sizes().map(|(size, _)| size).collect(),
+            freeze: self.freeze.iter_sizes().map(|(size, _)| size).collect(),
+        }
+    }
+
+    /// Deserialize a VerifierKeySet from its canonical serialization and validate() both of its
+    /// KeySets.
+    pub fn from_bytes_checked(bytes: &[u8]) -> Result<Self, Error> {
+        let set: Self = CanonicalDeserialize::deserialize(bytes)
+            .map_err(|source| Error::Deserialization { source })?;
+        set.xfr.validate()?;
+        set.freeze.validate()?;
+        Ok(set)
+    }
+
+    /// Write this VerifierKeySet to `writer` in a [KeySetEnvelope] tagged with [CURRENT_VERSION].
+    pub fn serialize_versioned<W: Write>(&self, writer: W) -> Result<(), SerializationError> {
+        serialize_versioned(self, writer)
+    }
+
+    /// Read a VerifierKeySet written by serialize_versioned() from `reader`.
+    ///
+    /// Fails with [Error::UnsupportedVersion] if the envelope was written with a different format
+    /// version.
+    pub fn deserialize_versioned<R: Read>(reader: R) -> Result<Self, Error> {
+        Ok(KeySetEnvelope::deserialize_versioned(reader)?.payload)
+    }
+}
+
+/// The version of the serialization format of key sets.
+///
+/// This is written at the start of every [KeySetEnvelope], and should be incremented whenever the
+/// canonical serialization of a key set changes, so that readers can detect data written in an
+/// older or newer format.
+pub const CURRENT_VERSION: u16 = 1;
+
+/// A serialized key set, tagged with the version of its serialization format.
+///
+/// The canonical serialization of an envelope is the version, followed by the canonical
+/// serialization of the payload.
+#[derive(Debug, Clone, CanonicalSerialize, CanonicalDeserialize, PartialEq, Eq)]
+pub struct KeySetEnvelope<T: CanonicalSerialize + CanonicalDeserialize> {
+    pub version: u16,
+    pub payload: T,
+}
+
+impl<T: CanonicalSerialize + CanonicalDeserialize> KeySetEnvelope<T> {
+    /// Wrap `payload` in an envelope tagged with [CURRENT_VERSION].
+    pub fn new(payload: T) -> Self {
+        Self {
+            version: CURRENT_VERSION,
+            payload,
+        }
+    }
+
+    /// Read an envelope from `reader`, checking its version before deserializing the payload.
+    ///
+    /// Fails with [Error::UnsupportedVersion] if the version is not [CURRENT_VERSION].
+    pub fn deserialize_versioned<R: Read>(mut reader: R) -> Result<Self, Error> {
+        let version: u16 = CanonicalDeserialize::deserialize(&mut reader)
+            .map_err(|source| Error::Deserialization { source })?;
+        if version != CURRENT_VERSION {
+            return Err(Error::UnsupportedVersion { version });
+        }
+        let payload = CanonicalDeserialize::deserialize(&mut reader)
+            .map_err(|source| Error::Deserialization { source })?;
+        Ok(Self { version, payload })
+    }
+}
+
+/// Write `payload` in the format of a [KeySetEnvelope] without taking ownership of it.
+#[cfg(feature = "std")]
+fn serialize_versioned<T: CanonicalSerialize, W: Write>(
+    payload: &T,
+    mut writer: W,
+) -> Result<(), SerializationError> {
+    CanonicalSerialize::serialize(&CURRENT_VERSION, &mut writer)?;
+    CanonicalSerialize::serialize(payload, &mut writer)
+}
+
+#[cfg(feature = "serde")]
+impl<'a, Order: KeyOrder + Serialize + for<'de> Deserialize<'de>> ProverKeySet<'a, Order> {
+    /// Serialize this ProverKeySet as JSON.
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(self)
+    }
+
+    /// Deserialize a ProverKeySet from JSON produced by to_json().
+    pub fn from_json(s: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(s)
+    }
+
+    /// Serialize this ProverKeySet with bincode, using the configuration `options`.
+    ///
+    /// See [KeySet::to_bincode] for the configuration used by the `Committable` implementation.
+    pub fn to_bincode<O: bincode::Options>(&self, options: O) -> Result<Vec<u8>, bincode::Error> {
+        options.serialize(self)
+    }
+
+    /// Deserialize a ProverKeySet produced by to_bincode() with the same `options`.
+    pub fn from_bincode<O: bincode::Options>(
+        bytes: &[u8],
+        options: O,
+    ) -> Result<Self, bincode::Error> {
+        options.deserialize(bytes)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<Order: KeyOrder + Serialize + for<'de> Deserialize<'de>> VerifierKeySet<Order> {
+    /// Serialize this VerifierKeySet as JSON.
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(self)
+    }
+
+    /// Deserialize a VerifierKeySet from JSON produced by to_json().
+    pub fn from_json(s: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(s)
+    }
+
+    /// Serialize this VerifierKeySet with bincode, using the configuration `options`.
+    ///
+    /// See [KeySet::to_bincode] for the configuration used by the `Committable` implementation.
+    pub fn to_bincode<O: bincode::Options>(&self, options: O) -> Result<Vec<u8>, bincode::Error> {
+        options.serialize(self)
+    }
+
+    /// Deserialize a VerifierKeySet produced by to_bincode() with the same `options`.
+    pub fn from_bincode<O: bincode::Options>(
+        bytes: &[u8],
+        options: O,
+    ) -> Result<Self, bincode::Error> {
+        options.deserialize(bytes)
+    }
+}
+
+/// The bytes committed to by the `Committable` implementations: the bincode encoding of `value`.
+///
+/// `RawCommitmentBuilder` only accepts a contiguous byte slice, so the entire encoding has to be
+/// held in memory while committing, which for a large key set is about as much memory again as the
+/// keys themselves. The buffer is allocated at its exact size up front, which at least avoids
+/// briefly needing twice that while a growing `Vec` is reallocated.
+#[cfg(feature = "serde")]
+fn commitment_bytes<T: Serialize>(value: &T) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(bincode::serialized_size(value).unwrap() as usize);
+    bincode::serialize_into(&mut bytes, value).unwrap();
+    bytes
+}
+
+#[cfg(feature = "serde")]
+impl<K: SizedKey + Serialize + for<'a> Deserialize<'a>, Order: KeyOrder> Committable
+    for KeySet<K, Order>
+{
+    fn commit(&self) -> Commitment<Self> {
+        commit::RawCommitmentBuilder::new("KeySet Comm")
+            .var_size_bytes(&commitment_bytes(self))
+            .finalize()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl VerifierKeySet {
+    /// Commit to this VerifierKeySet under a custom domain separator.
+    ///
+    /// This can be used to derive distinct commitments for different logical key sets, such as
+    /// the key sets for different networks. commit() is equivalent to
+    /// `commit_with_tag("VerifCRS Comm")`.
+    pub fn commit_with_tag(&self, tag: &str) -> Commitment<Self> {
+        commit::RawCommitmentBuilder::new(tag)
+            .var_size_bytes(&commitment_bytes(self))
+            .finalize()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Committable for VerifierKeySet {
+    fn commit(&self) -> Commitment<Self> {
+        self.commit_with_tag("VerifCRS Comm")
+    }
+}
+
+/// A [VerifierKeySet] which computes its commitment at most once.
+///
+/// Committing to a VerifierKeySet serializes every key in it, which is expensive to repeat. Since
+/// the fields of VerifierKeySet are public, it cannot cache its own commitment without the cache
+/// going stale when a field is modified. This wrapper only gives out shared references to the
+/// set, so the cached commitment is always valid. To modify the set, take it back with
+/// into_inner().
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone)]
+pub struct CachedVerifierKeySet {
+    set: VerifierKeySet,
+    commitment: OnceLock<Commitment<VerifierKeySet>>,
+}
+
+#[cfg(feature = "serde")]
+impl CachedVerifierKeySet {
+    pub fn new(set: VerifierKeySet) -> Self {
+        Self {
+            set,
+            commitment: OnceLock::new(),
+        }
+    }
+
+    /// The wrapped VerifierKeySet.
+    pub fn get(&self) -> &VerifierKeySet {
+        &self.set
+    }
+
+    /// Unwrap the VerifierKeySet, discarding the cached commitment.
+    pub fn into_inner(self) -> VerifierKeySet {
+        self.set
+    }
+
+    /// The commitment to the wrapped VerifierKeySet, computing it on the first call.
+    pub fn commit(&self) -> Commitment<VerifierKeySet> {
+        *self.commitment.get_or_init(|| self.set.commit())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<VerifierKeySet> for CachedVerifierKeySet {
+    fn from(set: VerifierKeySet) -> Self {
+        Self::new(set)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'a> Committable for ProverKeySet<'a> {
+    fn commit(&self) -> Commitment<Self> {
+        commit::RawCommitmentBuilder::new("ProverCRS Comm")
+            .var_size_bytes(&commitment_bytes(self))
+            .finalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug, PartialEq, Eq, Hash, CanonicalSerialize, CanonicalDeserialize)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    struct TestKey(usize, usize);
+
+    impl KeyShape for TestKey {
+        fn num_inputs(&self) -> usize {
+            self.0
+        }
+
+        fn num_outputs(&self) -> usize {
+            self.1
+        }
+    }
+
+    impl SizedKey for TestKey {}
+
+    /// A key with a payload, so that tests can tell apart different keys of the same size.
+    #[derive(Clone, Debug, PartialEq, Eq, Hash, CanonicalSerialize, CanonicalDeserialize)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    struct TaggedKey(usize, usize, Vec<u8>);
+
+    impl KeyShape for TaggedKey {
+        fn num_inputs(&self) -> usize {
+            self.0
+        }
+
+        fn num_outputs(&self) -> usize {
+            self.1
+        }
+    }
+
+    impl SizedKey for TaggedKey {}
+
+    /// The depth of the record Merkle tree in the CAP circuits built by these tests. It only
+    /// affects the size of the circuits, so it is kept small.
+    #[cfg(feature = "std")]
+    const TREE_DEPTH: u8 = 2;
+
+    /// Proving and verifying keys for a few small CAP circuits: transfers with 1 or 2 inputs and 2
+    /// outputs, and freezes with 2 inputs and outputs.
+    ///
+    /// Generating the SRS and the keys is slow, so they are shared by every test which needs real
+    /// keys.
+    #[cfg(feature = "std")]
+    fn cap_key_sets() -> &'static (ProverKeySet<'static>, VerifierKeySet) {
+        use jf_cap::{
+            proof::{freeze, mint, transfer, universal_setup_for_staging, UniversalParam},
+            structs::NoteType,
+            utils::compute_universal_param_size,
+        };
+        use rand_chacha::{rand_core::SeedableRng, ChaChaRng};
+
+        static SRS: OnceLock<UniversalParam> = OnceLock::new();
+        static KEYS: OnceLock<(ProverKeySet<'static>, VerifierKeySet)> = OnceLock::new();
+        KEYS.get_or_init(|| {
+            let srs = SRS.get_or_init(|| {
+                let max_degree = compute_universal_param_size(NoteType::Transfer, 2, 2, TREE_DEPTH)
+                    .unwrap()
+                    .max(mint_param_size())
+                    .max(compute_universal_param_size(NoteType::Freeze, 2, 2, TREE_DEPTH).unwrap());
+                universal_setup_for_staging(max_degree, &mut ChaChaRng::from_seed([0; 32])).unwrap()
+            });
+            let (mint_pk, mint_vk, _) = mint::preprocess(srs, TREE_DEPTH).unwrap();
+            let xfr = [1, 2].iter().map(|&num_inputs| {
+                let (pk, vk, _) = transfer::preprocess(srs, num_inputs, 2, TREE_DEPTH).unwrap();
+                (pk, vk)
+            });
+            let (freeze_pk, freeze_vk, _) = freeze::preprocess(srs, 2, TREE_DEPTH).unwrap();
+            ProverKeySet::from_preprocessed(
+                (mint_pk, mint_vk),
+                xfr,
+                core::iter::once((freeze_pk, freeze_vk)),
+            )
+            .unwrap()
+        })
+    }
+
+    /// The size of the SRS jf_cap needs for a mint circuit of size mint_shape().
+    #[cfg(feature = "std")]
+    fn mint_param_size() -> usize {
+        use jf_cap::{structs::NoteType, utils::compute_universal_param_size};
+
+        let (num_inputs, num_outputs) = mint_shape();
+        compute_universal_param_size(NoteType::Mint, num_inputs, num_outputs, TREE_DEPTH).unwrap()
+    }
+
+    fn key_set<Order: KeyOrder>(sizes: &[(usize, usize)]) -> KeySet<TestKey, Order> {
+        KeySet::new(
+            sizes
+                .iter()
+                .map(|&(num_inputs, num_outputs)| TestKey(num_inputs, num_outputs)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_len() {
+        let keys = key_set::<OrderByInputs>(&[(1, 2)]);
+        assert_eq!(keys.len(), 1);
+        assert!(!keys.is_empty());
+
+        let keys = key_set::<OrderByInputs>(&[(1, 2), (2, 2), (3, 3)]);
+        assert_eq!(keys.len(), 3);
+        assert!(!keys.is_empty());
+    }
+
+    #[test]
+    fn test_new_and_lookup() {
+        // Nothing here needs `std`, so this also runs with only the `alloc` feature.
+        let keys =
+            KeySet::<TestKey>::new(vec![TestKey(3, 2), TestKey(1, 2), TestKey(2, 3)].into_iter())
+                .unwrap();
+        assert_eq!(keys.len(), 3);
+        assert_eq!(keys.min_size(), (1, 2));
+        assert_eq!(keys.max_size(), (3, 2));
+        assert_eq!(keys.get(2, 3), Some(&TestKey(2, 3)));
+        assert_eq!(keys.get(2, 2), None);
+        assert_eq!(keys.best_fit_key(2, 2).unwrap(), (2, 3, &TestKey(2, 3)));
+        assert_eq!(keys.best_fit_key(3, 1).unwrap(), (3, 2, &TestKey(3, 2)));
+        assert_eq!(
+            keys.best_fit_key(3, 3),
+            Err(BestFitError {
+                requested: (3, 3),
+                largest_available: (3, 2),
+                budget_exhausted: false,
+            })
+        );
+    }
+
+    #[test]
+    fn test_try_max_size() {
+        let keys = key_set::<OrderByInputs>(&[(1, 2), (3, 2)]);
+        assert_eq!(keys.try_max_size(), Some((3, 2)));
+        assert_eq!(keys.max_size(), (3, 2));
+
+        // A serialized KeySet with no keys, as might be read from a corrupted file.
+        let empty = <KeySet<TestKey> as CanonicalDeserialize>::deserialize(&0u64.to_le_bytes()[..])
+            .unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(empty.try_max_size(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "KeySet must contain at least one key")]
+    fn test_max_size_of_default() {
+        KeySet::<TestKey>::default().max_size();
+    }
+
+    #[test]
+    fn test_canonical_round_trip() {
+        // This only uses ark-serialize, so it also runs without the `serde` feature.
+        let keys = key_set::<OrderByInputs>(&[(1, 2), (2, 2), (3, 1)]);
+        let mut bytes = Vec::new();
+        CanonicalSerialize::serialize(&keys, &mut bytes).unwrap();
+        assert_eq!(bytes.len(), CanonicalSerialize::serialized_size(&keys));
+        let deserialized: KeySet<TestKey> = CanonicalDeserialize::deserialize(&bytes[..]).unwrap();
+        assert_eq!(deserialized, keys);
+        assert_eq!(
+            deserialized.best_fit_key(2, 1).unwrap(),
+            (2, 2, &TestKey(2, 2))
+        );
+    }
+
+    #[test]
+    fn test_empty_key_set() {
+        let mut empty = KeySet::<TestKey>::default();
+        let other = key_set::<OrderByInputs>(&[(1, 2)]);
+        let no_fit = BestFitError {
+            requested: (1, 1),
+            largest_available: (0, 0),
+            budget_exhausted: false,
+        };
+
+        assert_eq!(empty.len(), 0);
+        assert!(empty.is_empty());
+        assert_eq!(empty.try_max_size(), None);
+        assert_eq!(empty.try_min_size(), None);
+        assert_eq!(empty.try_input_range(), None);
+        assert_eq!(empty.try_output_range(), None);
+        assert_eq!(empty.suggest_next_shape(), None);
+        assert_eq!(empty.first(), None);
+        assert_eq!(empty.last(), None);
+        assert_eq!(empty.worst_fit_key(), None);
+        assert_eq!(empty.get(1, 1), None);
+        assert_eq!(empty.exact_fit_key(1, 1), None);
+        assert!(!empty.contains_size(1, 1));
+        assert!(!empty.covers_size(1, 1));
+        assert_eq!(empty.best_fit_key(1, 1), Err(no_fit));
+        assert_eq!(empty.peek_best_fit_size(1, 1), Err(no_fit));
+        assert_eq!(empty.best_fit_key_with_budget(1, 1, 10), Err(no_fit));
+        assert_eq!(empty.best_fit_key_mut(1, 1).err(), Some(no_fit));
+        assert!(matches!(
+            empty.best_fit_or_split(1, 1),
+            BestFitPlan::Split {
+                most_inputs: None,
+                most_outputs: None
+            }
+        ));
+        assert_eq!(empty.range_fit(1, 1).count(), 0);
+        assert_eq!(empty.best_fit_inputs_exact_outputs(1, 1), None);
+        assert_eq!(empty.best_fit_outputs_exact_inputs(1, 1), None);
+        assert_eq!(empty.nearest_key(1, 1), None);
+        assert_eq!(empty.lower_bound_key(1, 1), None);
+        assert_eq!(empty.upper_bound_key(1, 1), None);
+        assert_eq!(empty.get_range((0, 0), (10, 10)).count(), 0);
+        assert_eq!(empty.get_mut(1, 1), None);
+        assert!(!empty.entry(1, 1).is_occupied());
+        assert_eq!(empty.iter().count(), 0);
+        assert_eq!(empty.iter_mut().count(), 0);
+        assert_eq!(empty.iter_sizes().count(), 0);
+        assert_eq!(empty.iter_canonical().count(), 0);
+        assert!(empty.keys_by_inputs().is_empty());
+        assert!(empty.keys_by_outputs().is_empty());
+        assert!(empty.input_histogram().is_empty());
+        assert!(empty.output_histogram().is_empty());
+        assert!(empty.coverage_grid().is_empty());
+        assert!(empty.shape_difference(&other).is_empty());
+        assert!(empty.shape_intersection(&other).is_empty());
+        assert!(!empty.is_superset_of(&other));
+        assert!(other.is_superset_of(&empty));
+        assert!(!empty.any_shape(|_, _| true));
+        assert!(empty.all_shapes(|_, _| false));
+        assert_eq!(empty.count_shapes(|_, _| true), 0);
+        assert_eq!(
+            empty.stats(),
+            KeySetStats {
+                len: 0,
+                min_size: None,
+                max_size: None,
+                input_range: None,
+                output_range: None,
+                serialized_size: empty.serialized_size(),
+            }
+        );
+        assert!(matches!(empty.validate(), Err(Error::NoKeys)));
+        assert!(matches!(empty.map(|key| key.clone()), Err(Error::NoKeys)));
+        let _ = alloc::format!("{:?} {:?}", empty, empty.debug_full());
+
+        let mut bytes = Vec::new();
+        empty.serialize_to(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), empty.serialized_size());
+        assert_eq!(
+            KeySet::<TestKey>::deserialize_from(&bytes[..]).unwrap(),
+            empty
+        );
+        assert!(matches!(
+            KeySet::<TestKey>::from_bytes_checked(&bytes),
+            Err(Error::NoKeys)
+        ));
+
+        assert_eq!(empty.remove(1, 1), None);
+        empty.retain(|_, _, _| true);
+        empty.prune_to_max_size();
+        empty.retain_largest_n(1);
+        empty.compact();
+        empty.clear();
+        let (small, large) = empty.clone().split_at_size(1, 1);
+        assert!(small.is_empty() && large.is_empty());
+        assert!(empty.clone().reorder::<OrderByOutputs>().is_empty());
+        assert_eq!(empty.clone().into_shared().len(), 0);
+        assert_eq!(empty.clone().into_pairs().count(), 0);
+        assert!(empty.clone().into_inner().is_empty());
+
+        // Keys can be added back to an empty KeySet.
+        assert_eq!(empty.replace(TestKey(1, 2)), None);
+        empty.merge(other.clone(), |existing, _| existing);
+        assert_eq!(empty, other);
+    }
+
+    #[test]
+    #[should_panic(expected = "KeySet must contain at least one key")]
+    fn test_min_size_of_default() {
+        KeySet::<TestKey>::default().min_size();
+    }
+
+    #[test]
+    #[should_panic(expected = "KeySet must contain at least one key")]
+    fn test_input_range_of_default() {
+        KeySet::<TestKey>::default().input_range();
+    }
+
+    #[test]
+    #[should_panic(expected = "KeySet must contain at least one key")]
+    fn test_output_range_of_default() {
+        KeySet::<TestKey>::default().output_range();
+    }
+
+    #[test]
+    fn test_error_display() {
+        let err =
+            KeySet::<TestKey>::new(vec![TestKey(1, 2), TestKey(1, 2)].into_iter()).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::DuplicateKeys {
+                num_inputs: 1,
+                num_outputs: 2
+            }
+        ));
+        assert_eq!(
+            alloc::format!("{}", err),
+            "multiple keys with size (1 inputs, 2 outputs)"
+        );
+        assert_eq!(
+            alloc::format!("{}", Error::NoKeys),
+            "a key set must contain at least one key"
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_std_error() {
+        fn boxed<E: std::error::Error + Send + Sync + 'static>(
+            err: E,
+        ) -> Box<dyn std::error::Error + Send + Sync> {
+            Box::new(err)
+        }
+
+        let err = boxed(Error::NoKeys);
+        assert_eq!(err.to_string(), "a key set must contain at least one key");
+        assert!(err.source().is_none());
+        assert!(err.downcast_ref::<Error>().is_some());
+    }
+
+    #[test]
+    fn test_validate() {
+        key_set::<OrderByInputs>(&[(1, 2), (3, 2)])
+            .validate()
+            .unwrap();
+        assert!(matches!(
+            KeySet::<TestKey>::default().validate(),
+            Err(Error::NoKeys)
+        ));
+
+        // A (3, 2) key indexed as if it were (1, 2).
+        let mut index = BTreeMap::new();
+        index.insert(OrderByInputs::sort_key(1, 2), TestKey(3, 2));
+        assert!(matches!(
+            KeySet::<TestKey>::from_inner(index),
+            Err(Error::CorruptIndex {
+                num_inputs: 3,
+                num_outputs: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn test_from_bytes_checked() {
+        let keys = key_set::<OrderByInputs>(&[(1, 2), (3, 2)]);
+        let mut bytes = Vec::new();
+        keys.serialize_to(&mut bytes).unwrap();
+        assert_eq!(KeySet::<TestKey>::from_bytes_checked(&bytes).unwrap(), keys);
+        assert!(matches!(
+            KeySet::<TestKey>::from_bytes_checked(&bytes[..bytes.len() - 1]),
+            Err(Error::Deserialization { .. })
+        ));
+
+        // Well-formed bytes whose index disagrees with the size of the key: the sort key of
+        // (1, 2), followed by a (3, 2) key.
+        let mut index = BTreeMap::new();
+        index.insert(OrderByInputs::sort_key(1, 2), TestKey(3, 2));
+        let mut bytes = Vec::new();
+        CanonicalSerialize::serialize(&index, &mut bytes).unwrap();
+        let unchecked: KeySet<TestKey> = CanonicalDeserialize::deserialize(&bytes[..]).unwrap();
+        assert_eq!(unchecked.get(1, 2), Some(&TestKey(3, 2)));
+        assert!(matches!(
+            KeySet::<TestKey>::from_bytes_checked(&bytes),
+            Err(Error::CorruptIndex {
+                num_inputs: 3,
+                num_outputs: 2
+            })
+        ));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_verifier_from_bytes_checked() {
+        let (_, verifier) = cap_key_sets();
+        let mut bytes = Vec::new();
+        CanonicalSerialize::serialize(verifier, &mut bytes).unwrap();
+        assert_eq!(
+            &VerifierKeySet::<OrderByInputs>::from_bytes_checked(&bytes).unwrap(),
+            verifier
+        );
+
+        // Move the (2, 2) transfer key to the index entry of the (1, 2) key.
+        let mut xfr = verifier.xfr.clone().into_inner();
+        let key = xfr.remove(&OrderByInputs::sort_key(2, 2)).unwrap();
+        xfr.insert(OrderByInputs::sort_key(1, 2), key);
+        let mut bytes = Vec::new();
+        CanonicalSerialize::serialize(&verifier.mint, &mut bytes).unwrap();
+        CanonicalSerialize::serialize(&xfr, &mut bytes).unwrap();
+        CanonicalSerialize::serialize(&verifier.freeze, &mut bytes).unwrap();
+        assert!(matches!(
+            VerifierKeySet::<OrderByInputs>::from_bytes_checked(&bytes),
+            Err(Error::CorruptIndex {
+                num_inputs: 2,
+                num_outputs: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn test_deterministic_serialization() {
+        let sizes = [(3, 2), (1, 2), (2, 3), (5, 1), (2, 2)];
+        let forward = KeySet::<TestKey>::new(sizes.iter().map(|&(i, o)| TestKey(i, o))).unwrap();
+        let backward =
+            KeySet::<TestKey>::new(sizes.iter().rev().map(|&(i, o)| TestKey(i, o))).unwrap();
+        let mut inserted = KeySet::<TestKey>::default();
+        for &(i, o) in [(2, 2), (5, 1), (1, 2), (3, 2), (2, 3)].iter() {
+            inserted.insert(TestKey(i, o)).unwrap();
+        }
+
+        let bytes = |keys: &KeySet<TestKey>| {
+            let mut bytes = Vec::new();
+            CanonicalSerialize::serialize(keys, &mut bytes).unwrap();
+            bytes
+        };
+        assert_eq!(bytes(&forward), bytes(&backward));
+        assert_eq!(bytes(&forward), bytes(&inserted));
+    }
+
+    #[test]
+    fn test_deserialize_bounded() {
+        let keys = key_set::<OrderByInputs>(&[(1, 2), (3, 2)]);
+        let mut bytes = Vec::new();
+        keys.serialize_to(&mut bytes).unwrap();
+        assert_eq!(
+            KeySet::<TestKey>::deserialize_bounded(&bytes[..], 2).unwrap(),
+            keys
+        );
+        assert!(matches!(
+            KeySet::<TestKey>::deserialize_bounded(&bytes[..], 1),
+            Err(Error::TooManyKeys {
+                num_keys: 2,
+                max_keys: 1
+            })
+        ));
+
+        // A stream claiming an enormous number of keys, followed by a single key, is rejected
+        // before anything is allocated for them.
+        let mut bytes = u64::MAX.to_le_bytes().to_vec();
+        CanonicalSerialize::serialize(&OrderByInputs::sort_key(1, 2), &mut bytes).unwrap();
+        CanonicalSerialize::serialize(&TestKey(1, 2), &mut bytes).unwrap();
+        assert!(matches!(
+            KeySet::<TestKey>::deserialize_bounded(&bytes[..], 1000),
+            Err(Error::TooManyKeys {
+                num_keys: u64::MAX,
+                max_keys: 1000
+            })
+        ));
+
+        // A count within the bound is still checked against the data that follows.
+        let mut bytes = 3u64.to_le_bytes().to_vec();
+        CanonicalSerialize::serialize(&OrderByInputs::sort_key(1, 2), &mut bytes).unwrap();
+        CanonicalSerialize::serialize(&TestKey(1, 2), &mut bytes).unwrap();
+        assert!(matches!(
+            KeySet::<TestKey>::deserialize_bounded(&bytes[..], 1000),
+            Err(Error::Deserialization { .. })
+        ));
+    }
+
+    #[test]
+    fn test_key_shape_of_reference() {
+        fn total_size<S: KeyShape>(keys: &[S]) -> (usize, usize) {
+            keys.iter().fold((0, 0), |(num_inputs, num_outputs), key| {
+                (
+                    num_inputs + key.num_inputs(),
+                    num_outputs + key.num_outputs(),
+                )
+            })
+        }
+
+        let keys = key_set::<OrderByInputs>(&[(1, 2), (3, 2)]);
+        let refs: Vec<&TestKey> = keys.iter().collect();
+        assert_eq!(total_size(&refs), (4, 4));
+        assert_eq!(refs[1].num_inputs(), 3);
+    }
+
+    #[test]
+    fn test_reorder_into() {
+        fn sizes<Order: KeyOrder>(keys: &KeySet<TestKey, Order>) -> Vec<(usize, usize)> {
+            keys.iter_sizes().map(|(size, _)| size).collect()
+        }
+
+        let by_inputs = key_set::<OrderByInputs>(&[(1, 3), (2, 2), (3, 1)]);
+        let by_outputs: KeySet<TestKey, OrderByOutputs> = by_inputs.clone().into();
+        assert_eq!(sizes(&by_outputs), vec![(3, 1), (2, 2), (1, 3)]);
+        assert_eq!(
+            KeySet::<TestKey, OrderByInputs>::from(by_outputs),
+            by_inputs
+        );
+
+        let by_sum: KeySet<TestKey, OrderBySum> = by_inputs.clone().into();
+        assert_eq!(sizes(&by_sum), vec![(1, 3), (2, 2), (3, 1)]);
+        let by_max: KeySet<TestKey, OrderByMax> = by_sum.clone().into();
+        assert_eq!(sizes(&by_max), vec![(2, 2), (1, 3), (3, 1)]);
+        assert_eq!(KeySet::<TestKey, OrderBySum>::from(by_max), by_sum);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_shape_keys() {
+        let keys = key_set::<OrderByInputs>(&[(1, 2), (3, 2)]);
+        let json = keys.to_json().unwrap();
+        assert_eq!(json, r#"{"keys":{"1-2":[1,2],"3-2":[3,2]}}"#);
+        assert_eq!(KeySet::from_json(&json).unwrap(), keys);
+        // The sort keys are recomputed from the sizes, so the JSON does not depend on the order.
+        assert_eq!(
+            KeySet::<TestKey, OrderByOutputs>::from_json(&json).unwrap(),
+            keys.clone().reorder()
+        );
+
+        // The sequence of (sort key, key) pairs written by older versions is still accepted.
+        let old = r#"{"keys":[[[1,2],[1,2]],[[3,2],[3,2]]]}"#;
+        assert_eq!(KeySet::<TestKey>::from_json(old).unwrap(), keys);
+
+        // A size which is malformed or does not match its key is rejected.
+        assert!(KeySet::<TestKey>::from_json(r#"{"keys":{"1-3":[1,2]}}"#).is_err());
+        assert!(KeySet::<TestKey>::from_json(r#"{"keys":{"1x2":[1,2]}}"#).is_err());
+
+        // In either form, more than one key with the same size is rejected rather than overwritten.
+        let err = KeySet::<TestKey>::from_json(r#"{"keys":{"1-2":[1,2],"01-2":[1,2]}}"#)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("multiple keys with size (1 inputs, 2 outputs)"));
+        let err = KeySet::<TestKey>::from_json(r#"{"keys":[[[1,2],[1,2]],[[1,2],[1,2]]]}"#)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("multiple keys with size (1 inputs, 2 outputs)"));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_from_preprocessed() {
+        let (prover, verifier) = cap_key_sets();
+        prover.verify_against(verifier).unwrap();
+        assert_eq!(prover.shapes(), verifier.shapes());
+        assert_eq!(
+            prover
+                .xfr
+                .iter_sizes()
+                .map(|(size, _)| size)
+                .collect::<Vec<_>>(),
+            vec![(1, 2), (2, 2)]
+        );
+        assert_eq!(
+            verifier
+                .xfr
+                .iter_sizes()
+                .map(|(size, _)| size)
+                .collect::<Vec<_>>(),
+            vec![(1, 2), (2, 2)]
+        );
+        assert_eq!(
+            prover
+                .freeze
+                .iter_sizes()
+                .map(|(size, _)| size)
+                .collect::<Vec<_>>(),
+            vec![(2, 2)]
+        );
+        assert_eq!(
+            verifier
+                .freeze
+                .iter_sizes()
+                .map(|(size, _)| size)
+                .collect::<Vec<_>>(),
+            vec![(2, 2)]
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_mint_shape() {
+        use jf_cap::proof::{mint, universal_setup_for_staging};
+        use rand_chacha::{rand_core::SeedableRng, ChaChaRng};
+
+        // Mint keys do not report their size, so check that an SRS sized for a mint circuit of
+        // size mint_shape() is enough for jf_cap to build the mint circuit.
+        let srs =
+            universal_setup_for_staging(mint_param_size(), &mut ChaChaRng::from_seed([1; 32]))
+                .unwrap();
+        mint::preprocess(&srs, TREE_DEPTH).unwrap();
+
+        let (prover, verifier) = cap_key_sets();
+        assert_eq!(
+            (verifier.mint.num_inputs(), verifier.mint.num_outputs()),
+            mint_shape()
+        );
+        assert_eq!(prover.shapes().mint, mint_shape());
+        assert_eq!(verifier.shapes().mint, mint_shape());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_cached_commitment() {
+        fn assert_sync<T: Sync>() {}
+        assert_sync::<CachedVerifierKeySet>();
+
+        let (_, verifier) = cap_key_sets();
+        let cached = CachedVerifierKeySet::new(verifier.clone());
+        assert!(cached.commitment.get().is_none());
+        let commitment = cached.commit();
+        assert_eq!(commitment, verifier.commit());
+
+        // The second call reuses the cached commitment instead of serializing the set again.
+        assert!(cached.commitment.get().is_some());
+        assert_eq!(cached.commit(), commitment);
+        assert_eq!(cached.into_inner(), *verifier);
+    }
+
+    #[cfg(feature = "checksum")]
+    #[test]
+    fn test_checksum() {
+        let keys = key_set::<OrderByInputs>(&[(1, 2), (2, 2), (3, 3)]);
+        let mut bytes = Vec::new();
+        serialize_with_checksum(&keys, &mut bytes).unwrap();
+        assert_eq!(bytes.len(), keys.serialized_size() + 32);
+        assert_eq!(
+            deserialize_with_checksum::<KeySet<TestKey>, _>(bytes.as_slice()).unwrap(),
+            keys
+        );
+
+        // Flip a byte in the number of keys, which no longer parses, in the last key, and in the
+        // digest.
+        for &i in &[0, keys.serialized_size() - 1, bytes.len() - 1] {
+            let mut corrupt = bytes.clone();
+            corrupt[i] ^= 1;
+            assert!(matches!(
+                deserialize_with_checksum::<KeySet<TestKey>, _>(corrupt.as_slice()),
+                Err(Error::ChecksumMismatch)
+            ));
+        }
+        assert!(matches!(
+            deserialize_with_checksum::<KeySet<TestKey>, _>(&bytes[..31]),
+            Err(Error::Deserialization { .. })
+        ));
+    }
+
+    /// The number of times any CountedKey has been deserialized.
+    #[cfg(feature = "std")]
+    static COUNTED_KEY_LOADS: std::sync::atomic::AtomicUsize =
+        std::sync::atomic::AtomicUsize::new(0);
+
+    /// A TestKey which counts how many times it is deserialized, to check when LazyKeySet loads it.
+    #[cfg(feature = "std")]
+    #[derive(Debug, PartialEq, CanonicalSerialize)]
+    struct CountedKey(TestKey);
+
+    #[cfg(feature = "std")]
+    impl CanonicalDeserialize for CountedKey {
+        fn deserialize<R: Read>(reader: R) -> Result<Self, SerializationError> {
+            COUNTED_KEY_LOADS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(Self(<TestKey as CanonicalDeserialize>::deserialize(
+                reader,
+            )?))
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl KeyShape for CountedKey {
+        fn num_inputs(&self) -> usize {
+            self.0.num_inputs()
+        }
+
+        fn num_outputs(&self) -> usize {
+            self.0.num_outputs()
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl SizedKey for CountedKey {}
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_lazy_key_set_loads_on_demand() {
+        fn assert_sync<T: Sync>() {}
+        assert_sync::<LazyKeySet<CountedKey>>();
+
+        let keys: KeySet<CountedKey> = [(1, 2), (2, 2), (3, 3)]
+            .iter()
+            .map(|&(num_inputs, num_outputs)| CountedKey(TestKey(num_inputs, num_outputs)))
+            .collect();
+        let path = std::env::temp_dir().join(format!("key-set-lazy-{}.bin", std::process::id()));
+        keys.serialize_indexed(File::create(&path).unwrap())
+            .unwrap();
+
+        let lazy = LazyKeySet::<CountedKey>::open(&path).unwrap();
+        assert_eq!(lazy.len(), 3);
+        assert_eq!(
+            lazy.sizes().collect::<Vec<_>>(),
+            vec![(1, 2), (2, 2), (3, 3)]
+        );
+        assert_eq!(lazy.max_size(), Some((3, 3)));
+        // Indexing reads only the table, not the keys.
+        assert_eq!(
+            COUNTED_KEY_LOADS.load(std::sync::atomic::Ordering::SeqCst),
+            0
+        );
+        assert_eq!(lazy.num_loaded(), 0);
+
+        // Only the requested key is loaded, and only once.
+        let (num_inputs, num_outputs, key) = lazy.best_fit_key(2, 1).unwrap().unwrap();
+        assert_eq!((num_inputs, num_outputs), (2, 2));
+        assert_eq!(key, &CountedKey(TestKey(2, 2)));
+        assert_eq!(lazy.exact_fit_key(2, 2).unwrap(), Some(key));
+        assert_eq!(
+            COUNTED_KEY_LOADS.load(std::sync::atomic::Ordering::SeqCst),
+            1
+        );
+        assert_eq!(lazy.num_loaded(), 1);
+
+        assert_eq!(lazy.exact_fit_key(3, 2).unwrap(), None);
+        assert!(lazy.best_fit_key(4, 1).unwrap().is_none());
+        assert_eq!(
+            COUNTED_KEY_LOADS.load(std::sync::atomic::Ordering::SeqCst),
+            1
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_lazy_key_set_checks_table() {
+        let path =
+            std::env::temp_dir().join(format!("key-set-lazy-table-{}.bin", std::process::id()));
+
+        // An empty set has no largest size.
+        KeySet::<TestKey>::default()
+            .serialize_indexed(File::create(&path).unwrap())
+            .unwrap();
+        assert!(matches!(
+            LazyKeySet::<TestKey>::open(&path),
+            Err(Error::NoKeys)
+        ));
+
+        // A key listed under the wrong sort key is rejected when the table is read.
+        let mut bytes = Vec::new();
+        CanonicalSerialize::serialize(&1u64, &mut bytes).unwrap();
+        CanonicalSerialize::serialize(&(2usize, 1usize), &mut bytes).unwrap();
+        CanonicalSerialize::serialize(&(1usize, 2usize), &mut bytes).unwrap();
+        CanonicalSerialize::serialize(&(TestKey(1, 2).serialized_size() as u64), &mut bytes)
+            .unwrap();
+        CanonicalSerialize::serialize(&TestKey(1, 2), &mut bytes).unwrap();
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            LazyKeySet::<TestKey>::open(&path),
+            Err(Error::CorruptIndex {
+                num_inputs: 1,
+                num_outputs: 2
+            })
+        ));
+
+        // A key whose size does not match the table is rejected when it is loaded.
+        let mut bytes = Vec::new();
+        CanonicalSerialize::serialize(&1u64, &mut bytes).unwrap();
+        CanonicalSerialize::serialize(&(1usize, 2usize), &mut bytes).unwrap();
+        CanonicalSerialize::serialize(&(1usize, 2usize), &mut bytes).unwrap();
+        CanonicalSerialize::serialize(&(TestKey(1, 3).serialized_size() as u64), &mut bytes)
+            .unwrap();
+        CanonicalSerialize::serialize(&TestKey(1, 3), &mut bytes).unwrap();
+        std::fs::write(&path, &bytes).unwrap();
+        let lazy = LazyKeySet::<TestKey>::open(&path).unwrap();
+        assert!(matches!(
+            lazy.exact_fit_key(1, 2),
+            Err(Error::CorruptIndex {
+                num_inputs: 1,
+                num_outputs: 3
+            })
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_min_size() {
+        let sizes = [(1, 3), (2, 1), (3, 2)];
+        let by_inputs = key_set::<OrderByInputs>(&sizes);
+        assert_eq!(by_inputs.min_size(), (1, 3));
+        assert_eq!(by_inputs.try_min_size(), Some((1, 3)));
+        let by_outputs = key_set::<OrderByOutputs>(&sizes);
+        assert_eq!(by_outputs.min_size(), (2, 1));
+        assert_eq!(by_outputs.try_min_size(), Some((2, 1)));
+    }
+
+    #[test]
+    fn test_contains_size() {
+        let keys = key_set::<OrderByInputs>(&[(1, 2), (3, 2)]);
+        assert!(keys.contains_size(1, 2));
+        assert!(keys.contains_size(3, 2));
+        assert!(!keys.contains_size(2, 2));
+        assert!(!keys.contains_size(2, 1));
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut keys = key_set::<OrderByInputs>(&[(1, 2), (3, 2)]);
+        assert_eq!(keys.remove(2, 2), None);
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys.remove(1, 2), Some(TestKey(1, 2)));
+        assert_eq!(keys.remove(1, 2), None);
+        assert_eq!(keys.len(), 1);
+        assert!(!keys.contains_size(1, 2));
+        assert_eq!(keys.max_size(), (3, 2));
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut keys = key_set::<OrderByInputs>(&[(1, 2)]);
+        keys.insert(TestKey(3, 2)).unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys.get(3, 2), Some(&TestKey(3, 2)));
+        assert!(matches!(
+            keys.insert(TestKey(3, 2)),
+            Err(Error::DuplicateKeys {
+                num_inputs: 3,
+                num_outputs: 2
+            })
+        ));
+        assert_eq!(keys.len(), 2);
+    }
+
+    #[test]
+    fn test_extend() {
+        let mut keys =
+            KeySet::<TaggedKey>::new(vec![TaggedKey(1, 2, vec![0])].into_iter()).unwrap();
+        keys.extend(vec![TaggedKey(2, 2, vec![0]), TaggedKey(3, 2, vec![0])]);
+        assert_eq!(keys.len(), 3);
+        assert!(keys.contains_size(2, 2) && keys.contains_size(3, 2));
+
+        // A key whose size is already present is skipped, keeping the existing key.
+        keys.extend(vec![TaggedKey(1, 2, vec![1]), TaggedKey(4, 2, vec![1])]);
+        assert_eq!(keys.len(), 4);
+        assert_eq!(keys.get(1, 2), Some(&TaggedKey(1, 2, vec![0])));
+        assert_eq!(keys.get(4, 2), Some(&TaggedKey(4, 2, vec![1])));
+    }
+
+    #[test]
+    fn test_try_from_iter() {
+        let keys = KeySet::<TestKey>::try_from_iter(vec![TestKey(1, 2), TestKey(3, 2)]).unwrap();
+        assert_eq!(keys, key_set(&[(1, 2), (3, 2)]));
+        assert!(matches!(
+            KeySet::<TestKey>::try_from_iter(Vec::new()),
+            Err(Error::NoKeys)
+        ));
+        assert!(matches!(
+            KeySet::<TestKey>::try_from_iter(vec![TestKey(1, 2), TestKey(1, 2)]),
+            Err(Error::DuplicateKeys {
+                num_inputs: 1,
+                num_outputs: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn test_into_iter() {
+        let keys = key_set::<OrderByInputs>(&[(3, 2), (1, 2)]);
+        let mut borrowed = Vec::new();
+        for key in &keys {
+            borrowed.push(key.clone());
+        }
+        assert_eq!(borrowed, vec![TestKey(1, 2), TestKey(3, 2)]);
+        assert_eq!(keys.into_iter().collect::<Vec<_>>(), borrowed);
+    }
+
+    #[test]
+    fn test_iter_sizes() {
+        let keys = key_set::<OrderByInputs>(&[(1, 2), (2, 3), (3, 1)]);
+        let sizes = keys.iter_sizes().collect::<Vec<_>>();
+        assert_eq!(sizes.len(), 3);
+        for ((num_inputs, num_outputs), key) in sizes {
+            assert_eq!(
+                (num_inputs, num_outputs),
+                (key.num_inputs(), key.num_outputs())
+            );
+        }
+    }
+
+    #[test]
+    fn test_order_by_sum() {
+        let sizes = [(2, 6), (3, 3), (1, 1)];
+        assert_eq!(
+            key_set::<OrderBySum>(&sizes).best_fit_key(2, 2).unwrap(),
+            (3, 3, &TestKey(3, 3))
+        );
+        // For comparison, ordering by inputs prefers the key with fewer inputs, even though it is
+        // larger in total.
+        assert_eq!(
+            key_set::<OrderByInputs>(&sizes).best_fit_key(2, 2).unwrap(),
+            (2, 6, &TestKey(2, 6))
+        );
+        // Keys which are large in total but too small in one dimension are skipped.
+        assert_eq!(
+            key_set::<OrderBySum>(&[(1, 9), (4, 4)])
+                .best_fit_key(2, 2)
+                .unwrap(),
+            (4, 4, &TestKey(4, 4))
+        );
+    }
+
+    #[test]
+    fn test_order_by_max() {
+        let sizes = [(2, 6), (4, 4)];
+        assert_eq!(
+            key_set::<OrderByMax>(&sizes).best_fit_key(2, 2).unwrap(),
+            (4, 4, &TestKey(4, 4))
+        );
+        assert_eq!(
+            key_set::<OrderByInputs>(&sizes).best_fit_key(2, 2).unwrap(),
+            (2, 6, &TestKey(2, 6))
+        );
+    }
+
+    #[test]
+    fn test_reorder() {
+        let sizes = [(1, 3), (2, 2), (3, 1), (3, 3)];
+        let by_inputs = key_set::<OrderByInputs>(&sizes);
+        let by_outputs = by_inputs.clone().reorder::<OrderByOutputs>();
+        assert_eq!(by_outputs, key_set::<OrderByOutputs>(&sizes));
+        assert_eq!(by_outputs.len(), by_inputs.len());
+        for num_inputs in 0..=4 {
+            for num_outputs in 0..=4 {
+                let before = by_inputs.best_fit_key(num_inputs, num_outputs);
+                let after = by_outputs.best_fit_key(num_inputs, num_outputs);
+                // The orderings may pick different keys, but a key fits in one exactly when it
+                // fits in the other.
+                assert_eq!(before.is_ok(), after.is_ok());
+                if let Ok((key_inputs, key_outputs, _)) = after {
+                    assert!(key_inputs >= num_inputs && key_outputs >= num_outputs);
+                }
+            }
+        }
+        assert_eq!(by_outputs.reorder::<OrderByInputs>(), by_inputs);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_prover_commit() {
+        let (prover, _) = cap_key_sets();
+        assert_eq!(prover.commit(), prover.commit());
+        assert_eq!(prover.clone().commit(), prover.commit());
+
+        let mut modified = prover.clone();
+        modified.xfr.remove(1, 2).unwrap();
+        assert_ne!(modified.commit(), prover.commit());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_verifier_eq() {
+        let (_, verifier) = cap_key_sets();
+        let mut bytes = Vec::new();
+        CanonicalSerialize::serialize(verifier, &mut bytes).unwrap();
+        let deserialized: VerifierKeySet = CanonicalDeserialize::deserialize(&bytes[..]).unwrap();
+        assert_eq!(&deserialized, verifier);
+
+        let mut modified = verifier.clone();
+        modified.xfr.remove(1, 2).unwrap();
+        assert_ne!(&modified, verifier);
+    }
+
+    #[test]
+    fn test_best_fit_error() {
+        let keys = key_set::<OrderByInputs>(&[(1, 2), (3, 2)]);
+        let err = keys.best_fit_key(4, 1).unwrap_err();
+        assert_eq!(err.requested, (4, 1));
+        assert_eq!(err.largest_available, (3, 2));
+        assert!(!err.budget_exhausted);
+        assert_eq!(
+            keys.best_fit_key(2, 3).unwrap_err().largest_available,
+            (3, 2)
+        );
+    }
+
+    #[test]
+    fn test_range_fit() {
+        let keys = key_set::<OrderByInputs>(&[(1, 4), (2, 1), (2, 3), (3, 2), (4, 4)]);
+        assert_eq!(
+            keys.range_fit(2, 2).collect::<Vec<_>>(),
+            vec![
+                (2, 3, &TestKey(2, 3)),
+                (3, 2, &TestKey(3, 2)),
+                (4, 4, &TestKey(4, 4))
+            ]
+        );
+        assert_eq!(keys.range_fit(2, 2).next(), keys.best_fit_key(2, 2).ok());
+        assert_eq!(keys.range_fit(5, 1).count(), 0);
+    }
+
+    #[test]
+    fn test_worst_fit_key() {
+        let keys = key_set::<OrderByInputs>(&[(1, 4), (3, 2), (2, 2)]);
+        let (num_inputs, num_outputs, key) = keys.worst_fit_key().unwrap();
+        assert_eq!((num_inputs, num_outputs), keys.max_size());
+        assert_eq!(key, &TestKey(3, 2));
+        assert_eq!(KeySet::<TestKey>::default().worst_fit_key(), None);
+    }
+
+    #[test]
+    fn test_nearest_key() {
+        let keys = key_set::<OrderByInputs>(&[(2, 9), (3, 3), (5, 5)]);
+        assert_eq!(keys.best_fit_key(2, 2).unwrap(), (2, 9, &TestKey(2, 9)));
+        assert_eq!(keys.nearest_key(2, 2), Some((3, 3, &TestKey(3, 3))));
+
+        // When the best fit is also the tightest, they agree.
+        assert_eq!(keys.nearest_key(3, 3), Some((3, 3, &TestKey(3, 3))));
+        assert_eq!(keys.best_fit_key(3, 3).unwrap(), (3, 3, &TestKey(3, 3)));
+        assert_eq!(keys.nearest_key(6, 1), None);
+    }
+
+    #[test]
+    fn test_retain() {
+        let mut keys = key_set::<OrderByInputs>(&[(1, 2), (2, 2), (8, 2), (9, 1)]);
+        keys.retain(|num_inputs, _, _| num_inputs <= 2);
+        assert_eq!(keys, key_set(&[(1, 2), (2, 2)]));
+        keys.retain(|_, _, key| key.0 > 2);
+        assert!(keys.is_empty());
+    }
+
+    #[test]
+    fn test_get_mut() {
+        let mut keys = KeySet::<TaggedKey>::new(
+            vec![TaggedKey(1, 2, vec![0]), TaggedKey(3, 2, vec![0])].into_iter(),
+        )
+        .unwrap();
+        keys.get_mut(1, 2).unwrap().2.push(1);
+        assert_eq!(keys.get(1, 2), Some(&TaggedKey(1, 2, vec![0, 1])));
+        assert_eq!(keys.get(3, 2), Some(&TaggedKey(3, 2, vec![0])));
+        assert!(keys.get_mut(2, 2).is_none());
+    }
+
+    #[test]
+    fn test_iter_mut() {
+        let mut keys = KeySet::<TaggedKey>::new(
+            vec![TaggedKey(1, 2, vec![]), TaggedKey(3, 2, vec![])].into_iter(),
+        )
+        .unwrap();
+        for key in keys.iter_mut() {
+            key.2.push(key.0 as u8);
+        }
+        assert_eq!(
+            keys.iter().map(|key| key.2.clone()).collect::<Vec<_>>(),
+            vec![vec![1], vec![3]]
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_round_trip() {
+        let keys = key_set::<OrderByInputs>(&[(1, 2), (3, 2)]);
+        assert_eq!(KeySet::from_json(&keys.to_json().unwrap()).unwrap(), keys);
+
+        let (prover, verifier) = cap_key_sets();
+        assert_eq!(
+            &ProverKeySet::from_json(&prover.to_json().unwrap()).unwrap(),
+            prover
+        );
+        assert_eq!(
+            &VerifierKeySet::from_json(&verifier.to_json().unwrap()).unwrap(),
+            verifier
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_key_set_commit() {
+        let mut keys = key_set::<OrderByInputs>(&[(1, 2), (3, 2)]);
+        let comm = keys.commit();
+        assert_eq!(keys.commit(), comm);
+        assert_eq!(key_set::<OrderByInputs>(&[(3, 2), (1, 2)]).commit(), comm);
+
+        keys.insert(TestKey(2, 2)).unwrap();
+        assert_ne!(keys.commit(), comm);
+        keys.remove(2, 2).unwrap();
+        assert_eq!(keys.commit(), comm);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_commit_with_tag() {
+        let (_, verifier) = cap_key_sets();
+        assert_eq!(verifier.commit_with_tag("VerifCRS Comm"), verifier.commit());
+        assert_eq!(
+            verifier.commit_with_tag("mainnet"),
+            verifier.commit_with_tag("mainnet")
+        );
+        assert_ne!(
+            verifier.commit_with_tag("mainnet"),
+            verifier.commit_with_tag("testnet")
+        );
+        assert_ne!(verifier.commit_with_tag("mainnet"), verifier.commit());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_hash() {
+        use std::collections::HashSet;
+
+        let mut sets = HashSet::new();
+        assert!(sets.insert(key_set::<OrderByInputs>(&[(1, 2), (3, 2)])));
+        assert!(!sets.insert(key_set::<OrderByInputs>(&[(3, 2), (1, 2)])));
+        assert!(sets.insert(key_set::<OrderByInputs>(&[(1, 2)])));
+        assert_eq!(sets.len(), 2);
+
+        let (_, verifier) = cap_key_sets();
+        let mut verifiers = HashSet::new();
+        assert!(verifiers.insert(verifier.clone()));
+        assert!(!verifiers.insert(verifier.clone()));
+    }
+
+    #[test]
+    fn test_map() {
+        let keys = KeySet::<TaggedKey>::new(
+            vec![TaggedKey(1, 2, vec![0; 4]), TaggedKey(3, 2, vec![0; 8])].into_iter(),
+        )
+        .unwrap();
+        let summary = keys.map(|key| TestKey(key.0, key.1)).unwrap();
+        assert_eq!(summary, key_set(&[(1, 2), (3, 2)]));
+        assert_eq!(
+            summary
+                .iter_sizes()
+                .map(|(size, _)| size)
+                .collect::<Vec<_>>(),
+            keys.iter_sizes().map(|(size, _)| size).collect::<Vec<_>>()
+        );
+
+        // A mapping which does not preserve sizes can produce duplicates.
+        assert!(matches!(
+            keys.map(|key| TestKey(key.0.min(1), key.1)),
+            Err(Error::DuplicateKeys {
+                num_inputs: 1,
+                num_outputs: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut keys =
+            KeySet::<TestKey>::new_with_hint(vec![TestKey(1, 2), TestKey(3, 2)].into_iter(), 2)
+                .unwrap();
+        keys.clear();
+        assert!(keys.is_empty());
+        keys.insert(TestKey(2, 2)).unwrap();
+        assert_eq!(keys, key_set(&[(2, 2)]));
+    }
+
+    #[test]
+    fn test_merge() {
+        let tagged =
+            |sizes: &[(usize, usize)], tag: u8| {
+                KeySet::<TaggedKey>::new(sizes.iter().map(|&(num_inputs, num_outputs)| {
+                    TaggedKey(num_inputs, num_outputs, vec![tag])
+                }))
+                .unwrap()
+            };
+
+        let mut keys = tagged(&[(1, 2)], 0);
+        keys.merge(tagged(&[(2, 2), (3, 2)], 1), |_, _| unreachable!());
+        assert_eq!(keys.len(), 3);
+
+        // Keep the incoming key when both sets have a key of the same size.
+        keys.merge(tagged(&[(1, 2), (4, 2)], 2), |_, incoming| incoming);
+        assert_eq!(keys.len(), 4);
+        assert_eq!(keys.get(1, 2), Some(&TaggedKey(1, 2, vec![2])));
+        assert_eq!(keys.get(2, 2), Some(&TaggedKey(2, 2, vec![1])));
+        assert_eq!(keys.get(4, 2), Some(&TaggedKey(4, 2, vec![2])));
+    }
+
+    #[test]
+    fn test_shape_difference_and_intersection() {
+        let a = key_set::<OrderByInputs>(&[(1, 2), (2, 2), (3, 2)]);
+        let b = key_set::<OrderByOutputs>(&[(2, 2), (3, 2), (4, 1)]);
+        assert_eq!(a.shape_difference(&b), vec![(1, 2)]);
+        assert_eq!(b.shape_difference(&a), vec![(4, 1)]);
+        assert_eq!(a.shape_intersection(&b), vec![(2, 2), (3, 2)]);
+        assert_eq!(a.shape_intersection(&a), vec![(1, 2), (2, 2), (3, 2)]);
+        assert!(a.shape_difference(&a).is_empty());
+    }
+
+    #[test]
+    fn test_builder() {
+        let mut builder = KeySetBuilder::<TestKey>::new();
+        builder
+            .add(TestKey(1, 2))
+            .unwrap()
+            .add(TestKey(3, 2))
+            .unwrap();
+        assert!(matches!(
+            builder.add(TestKey(1, 2)),
+            Err(Error::DuplicateKeys {
+                num_inputs: 1,
+                num_outputs: 2
+            })
+        ));
+        assert!(matches!(
+            builder.add_all(vec![TestKey(2, 2), TestKey(3, 2)]),
+            Err(Error::DuplicateKeys {
+                num_inputs: 3,
+                num_outputs: 2
+            })
+        ));
+        assert_eq!(builder.build().unwrap(), key_set(&[(1, 2), (2, 2), (3, 2)]));
+
+        assert!(matches!(
+            KeySetBuilder::<TestKey>::new().build(),
+            Err(Error::NoKeys)
+        ));
+    }
+
+    #[test]
+    fn test_new_keeping_largest() {
+        let keys = KeySet::<TaggedKey>::new_keeping_largest(
+            vec![
+                TaggedKey(1, 2, vec![0]),
+                TaggedKey(1, 2, vec![1, 1]),
+                TaggedKey(3, 2, vec![0]),
+                TaggedKey(3, 2, vec![1]),
+            ]
+            .into_iter(),
+        )
+        .unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys.get(1, 2), Some(&TaggedKey(1, 2, vec![1, 1])));
+        // Keys which serialize to the same number of bytes keep the first one.
+        assert_eq!(keys.get(3, 2), Some(&TaggedKey(3, 2, vec![0])));
+    }
+
+    #[test]
+    fn test_serialized_size() {
+        let keys = KeySet::<TaggedKey>::new(
+            vec![TaggedKey(1, 2, vec![0; 3]), TaggedKey(3, 2, vec![0; 5])].into_iter(),
+        )
+        .unwrap();
+        let mut bytes = Vec::new();
+        keys.serialize_to(&mut bytes).unwrap();
+        assert_eq!(keys.serialized_size(), bytes.len());
+        assert_eq!(keys.stats().serialized_size, bytes.len());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_key_set_serialized_sizes() {
+        let (prover, verifier) = cap_key_sets();
+        let mut bytes = Vec::new();
+        CanonicalSerialize::serialize(prover, &mut bytes).unwrap();
+        assert_eq!(prover.serialized_size(), bytes.len());
+        let mut bytes = Vec::new();
+        CanonicalSerialize::serialize(verifier, &mut bytes).unwrap();
+        assert_eq!(verifier.serialized_size(), bytes.len());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_from_uncompressed_bytes_parallel() {
+        let (prover, _) = cap_key_sets();
+        let mut bytes = Vec::new();
+        CanonicalSerialize::serialize_uncompressed(prover, &mut bytes).unwrap();
+        let sequential: ProverKeySet =
+            CanonicalDeserialize::deserialize_uncompressed(&bytes[..]).unwrap();
+        let parallel =
+            ProverKeySet::<OrderByInputs>::from_uncompressed_bytes_parallel(&bytes).unwrap();
+        assert_eq!(parallel, sequential);
+        assert_eq!(&parallel, prover);
+        assert!(
+            ProverKeySet::<OrderByInputs>::from_uncompressed_bytes_parallel(
+                &bytes[..bytes.len() - 1]
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_serialize_to_stream() {
+        let keys = key_set::<OrderByInputs>(&[(1, 2), (2, 2), (3, 2)]);
+        let mut writer = Vec::new();
+        keys.serialize_to(&mut writer).unwrap();
+        let mut reader = &writer[..];
+        assert_eq!(
+            KeySet::<TestKey>::deserialize_from(&mut reader).unwrap(),
+            keys
+        );
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_dimension_ranges() {
+        let keys = key_set::<OrderByInputs>(&[(1, 5), (4, 2), (2, 3)]);
+        assert_eq!(keys.input_range(), (1, 4));
+        assert_eq!(keys.output_range(), (2, 5));
+        assert_eq!(keys.try_input_range(), Some((1, 4)));
+        assert_eq!(keys.try_output_range(), Some((2, 5)));
+        assert_eq!(KeySet::<TestKey>::default().try_input_range(), None);
+        assert_eq!(KeySet::<TestKey>::default().try_output_range(), None);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_lookup_aliases() {
+        let keys = key_set::<OrderByInputs>(&[(1, 2), (3, 2)]);
+        for &(num_inputs, num_outputs) in [(1, 2), (3, 2), (2, 2)].iter() {
+            let key = keys.get(num_inputs, num_outputs);
+            assert_eq!(keys.exact_fit_key(num_inputs, num_outputs), key);
+            assert_eq!(keys.key_for_size(num_inputs, num_outputs), key);
+        }
+    }
+
+    #[test]
+    fn test_replace() {
+        let mut keys =
+            KeySet::<TaggedKey>::new(vec![TaggedKey(1, 2, vec![0])].into_iter()).unwrap();
+        assert_eq!(
+            keys.replace(TaggedKey(1, 2, vec![1])),
+            Some(TaggedKey(1, 2, vec![0]))
+        );
+        assert_eq!(keys.get(1, 2), Some(&TaggedKey(1, 2, vec![1])));
+        assert_eq!(keys.replace(TaggedKey(3, 2, vec![1])), None);
+        assert_eq!(keys.len(), 2);
+    }
+
+    #[test]
+    fn test_try_from_vec() {
+        let keys = KeySet::<TestKey>::try_from(vec![TestKey(1, 2), TestKey(3, 2)]).unwrap();
+        assert_eq!(keys, key_set(&[(1, 2), (3, 2)]));
+        assert!(matches!(
+            KeySet::<TestKey>::try_from(Vec::new()),
+            Err(Error::NoKeys)
+        ));
+        assert!(matches!(
+            KeySet::<TestKey>::try_from(vec![TestKey(3, 2), TestKey(3, 2)]),
+            Err(Error::DuplicateKeys {
+                num_inputs: 3,
+                num_outputs: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn test_key_order_macro() {
+        key_order!(OrderByProduct, |num_inputs,
+                                    num_outputs|
+         -> (usize, usize, usize) {
+            (num_inputs * num_outputs, num_inputs, num_outputs)
+        });
+
+        let keys = key_set::<OrderByProduct>(&[(1, 6), (2, 2), (3, 3), (4, 1)]);
+        assert_eq!(OrderByProduct::name(), "OrderByProduct");
+        assert_eq!(
+            keys.iter_sizes().map(|(size, _)| size).collect::<Vec<_>>(),
+            vec![(2, 2), (4, 1), (1, 6), (3, 3)]
+        );
+        assert_eq!(keys.best_fit_key(2, 1).unwrap(), (2, 2, &TestKey(2, 2)));
+        assert_eq!(keys.best_fit_key(1, 3).unwrap(), (1, 6, &TestKey(1, 6)));
+    }
+
+    #[test]
+    fn test_keys_by_dimension() {
+        let keys = key_set::<OrderByInputs>(&[(1, 1), (1, 2), (2, 2), (3, 1), (3, 2), (3, 3)]);
+        let by_inputs = keys.keys_by_inputs();
+        assert_eq!(
+            by_inputs
+                .iter()
+                .map(|(&num_inputs, keys)| (num_inputs, keys.len()))
+                .collect::<Vec<_>>(),
+            vec![(1, 2), (2, 1), (3, 3)]
+        );
+        assert!(by_inputs[&3].iter().all(|key| key.0 == 3));
+        let by_outputs = keys.keys_by_outputs();
+        assert_eq!(
+            by_outputs
+                .iter()
+                .map(|(&num_outputs, keys)| (num_outputs, keys.len()))
+                .collect::<Vec<_>>(),
+            vec![(1, 2), (2, 3), (3, 1)]
+        );
+    }
+
+    #[test]
+    fn test_bound_keys() {
+        let keys = key_set::<OrderByInputs>(&[(1, 2), (3, 2)]);
+        // At an existing size, both bounds return its key.
+        assert_eq!(keys.lower_bound_key(1, 2), Some((1, 2, &TestKey(1, 2))));
+        assert_eq!(keys.upper_bound_key(3, 2), Some((3, 2, &TestKey(3, 2))));
+        // Between two sizes.
+        assert_eq!(keys.lower_bound_key(2, 2), Some((1, 2, &TestKey(1, 2))));
+        assert_eq!(keys.upper_bound_key(2, 2), Some((3, 2, &TestKey(3, 2))));
+        // Below the smallest size and above the largest.
+        assert_eq!(keys.lower_bound_key(0, 5), None);
+        assert_eq!(keys.upper_bound_key(0, 5), Some((1, 2, &TestKey(1, 2))));
+        assert_eq!(keys.lower_bound_key(4, 0), Some((3, 2, &TestKey(3, 2))));
+        assert_eq!(keys.upper_bound_key(4, 0), None);
+        // The bounds follow the sort key, so a size with more outputs can still be bounded
+        // above by a key with fewer.
+        assert_eq!(keys.upper_bound_key(1, 9), Some((3, 2, &TestKey(3, 2))));
+    }
+
+    #[test]
+    fn test_best_fit_key_mut() {
+        let mut keys = KeySet::<TaggedKey>::new(
+            vec![TaggedKey(1, 2, vec![]), TaggedKey(3, 2, vec![])].into_iter(),
+        )
+        .unwrap();
+        let (num_inputs, num_outputs, key) = keys.best_fit_key_mut(2, 1).unwrap();
+        assert_eq!((num_inputs, num_outputs), (3, 2));
+        key.2.push(1);
+        assert_eq!(keys.get(3, 2), Some(&TaggedKey(3, 2, vec![1])));
+        assert_eq!(
+            keys.best_fit_key_mut(4, 1).err(),
+            keys.best_fit_key(4, 1).err()
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_shapes() {
+        let (prover, verifier) = cap_key_sets();
+        for shapes in [prover.shapes(), verifier.shapes()].iter() {
+            assert_eq!(shapes.mint, mint_shape());
+            assert_eq!(
+                shapes.xfr,
+                prover
+                    .xfr
+                    .iter_sizes()
+                    .map(|(size, _)| size)
+                    .collect::<Vec<_>>()
+            );
+            assert_eq!(
+                shapes.freeze,
+                prover
+                    .freeze
+                    .iter_sizes()
+                    .map(|(size, _)| size)
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_verify_against() {
+        let (prover, verifier) = cap_key_sets();
+        prover.verify_against(verifier).unwrap();
+        prover
+            .verify_against(&verifier.clone().reorder::<OrderByOutputs>())
+            .unwrap();
+
+        let mut partial = prover.clone();
+        partial.xfr.remove(1, 2).unwrap();
+        match partial.verify_against(verifier) {
+            Err(Error::KeySetMismatch {
+                component,
+                missing_from_verifier,
+                missing_from_prover,
+            }) => {
+                assert_eq!(component, "transfer");
+                assert!(missing_from_verifier.is_empty());
+                assert_eq!(missing_from_prover, vec![(1, 2)]);
+            }
+            res => panic!("expected KeySetMismatch, got {:?}", res),
+        }
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_serialize_compressed_gzip() {
+        let (prover, _) = cap_key_sets();
+        let mut raw = Vec::new();
+        CanonicalSerialize::serialize(prover, &mut raw).unwrap();
+        let mut compressed = Vec::new();
+        prover.serialize_compressed(&mut compressed).unwrap();
+        assert!(compressed.len() < raw.len());
+        assert_eq!(
+            &ProverKeySet::<OrderByInputs>::deserialize_compressed(&compressed[..]).unwrap(),
+            prover
+        );
+        // Uncompressed bytes lack the gzip header.
+        assert!(ProverKeySet::<OrderByInputs>::deserialize_compressed(&raw[..]).is_err());
+    }
+
+    #[test]
+    fn test_entry() {
+        let mut keys =
+            KeySet::<TaggedKey>::new(vec![TaggedKey(1, 2, vec![0])].into_iter()).unwrap();
+
+        let entry = keys.entry(1, 2);
+        assert_eq!(entry.size(), (1, 2));
+        assert!(entry.is_occupied());
+        assert_eq!(
+            entry.or_insert_with(|| unreachable!()),
+            &TaggedKey(1, 2, vec![0])
+        );
+
+        let entry = keys.entry(3, 2);
+        assert!(!entry.is_occupied());
+        entry.or_insert_with(|| TaggedKey(3, 2, vec![1])).2.push(2);
+        assert_eq!(keys.get(3, 2), Some(&TaggedKey(3, 2, vec![1, 2])));
+        assert_eq!(keys.best_fit_key(2, 2).unwrap().0, 3);
+    }
+
+    #[test]
+    fn test_versioned_envelope() {
+        let keys = key_set::<OrderByInputs>(&[(1, 2), (3, 2)]);
+        let mut bytes = Vec::new();
+        CanonicalSerialize::serialize(&KeySetEnvelope::new(keys.clone()), &mut bytes).unwrap();
+        let envelope =
+            KeySetEnvelope::<KeySet<TestKey>>::deserialize_versioned(&bytes[..]).unwrap();
+        assert_eq!(envelope.version, CURRENT_VERSION);
+        assert_eq!(envelope.payload, keys);
+
+        let bumped = KeySetEnvelope {
+            version: CURRENT_VERSION + 1,
+            payload: keys,
+        };
+        let mut bytes = Vec::new();
+        CanonicalSerialize::serialize(&bumped, &mut bytes).unwrap();
+        assert!(matches!(
+            KeySetEnvelope::<KeySet<TestKey>>::deserialize_versioned(&bytes[..]),
+            Err(Error::UnsupportedVersion { version }) if version == CURRENT_VERSION + 1
+        ));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_key_sets_versioned() {
+        let (prover, verifier) = cap_key_sets();
+        let mut bytes = Vec::new();
+        prover.serialize_versioned(&mut bytes).unwrap();
+        assert_eq!(
+            &ProverKeySet::<OrderByInputs>::deserialize_versioned(&bytes[..]).unwrap(),
+            prover
+        );
+        let mut bytes = Vec::new();
+        verifier.serialize_versioned(&mut bytes).unwrap();
+        assert_eq!(
+            &VerifierKeySet::<OrderByInputs>::deserialize_versioned(&bytes[..]).unwrap(),
+            verifier
+        );
+
+        // The version is the first field of the envelope.
+        let mut bumped = bytes.clone();
+        bumped[..2].copy_from_slice(&(CURRENT_VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            VerifierKeySet::<OrderByInputs>::deserialize_versioned(&bumped[..]),
+            Err(Error::UnsupportedVersion { version }) if version == CURRENT_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn test_shape_queries() {
+        let keys = key_set::<OrderByInputs>(&[(1, 2), (2, 2), (3, 1)]);
+        assert!(keys.any_shape(|_, num_outputs| num_outputs == 2));
+        assert!(!keys.any_shape(|num_inputs, _| num_inputs > 3));
+        assert!(keys.all_shapes(|num_inputs, num_outputs| num_inputs + num_outputs <= 4));
+        assert!(!keys.all_shapes(|_, num_outputs| num_outputs == 2));
+        assert_eq!(keys.count_shapes(|_, num_outputs| num_outputs == 2), 2);
+        assert_eq!(keys.count_shapes(|_, _| false), 0);
+    }
+
+    #[test]
+    fn test_best_fit_exact_dimension() {
+        let by_inputs = key_set::<OrderByInputs>(&[(2, 5), (3, 2), (4, 2)]);
+        assert_eq!(
+            by_inputs.best_fit_key(2, 2).unwrap(),
+            (2, 5, &TestKey(2, 5))
+        );
+        assert_eq!(
+            by_inputs.best_fit_inputs_exact_outputs(2, 2),
+            Some((3, 2, &TestKey(3, 2)))
+        );
+        assert_eq!(by_inputs.best_fit_inputs_exact_outputs(2, 3), None);
+
+        let by_outputs = key_set::<OrderByOutputs>(&[(2, 5), (3, 1), (3, 3), (5, 2)]);
+        assert_eq!(
+            by_outputs.best_fit_key(3, 2).unwrap(),
+            (5, 2, &TestKey(5, 2))
+        );
+        assert_eq!(
+            by_outputs.best_fit_outputs_exact_inputs(3, 2),
+            Some((3, 3, &TestKey(3, 3)))
+        );
+        assert_eq!(by_outputs.best_fit_outputs_exact_inputs(4, 1), None);
+    }
+
+    #[test]
+    fn test_into_pairs() {
+        let keys = key_set::<OrderByInputs>(&[(3, 2), (1, 2)]);
+        let pairs = keys.into_pairs().collect::<Vec<_>>();
+        assert_eq!(
+            pairs,
+            vec![((1, 2), TestKey(1, 2)), ((3, 2), TestKey(3, 2))]
+        );
+        for ((num_inputs, num_outputs), key) in pairs {
+            assert_eq!((num_inputs, num_outputs), (key.0, key.1));
+        }
+    }
+
+    #[test]
+    fn test_prune_to_max_size() {
+        let mut keys = key_set::<OrderByInputs>(&[(1, 2), (3, 2), (2, 4)]);
+        let max_size = keys.max_size();
+        keys.prune_to_max_size();
+        assert_eq!(keys, key_set(&[max_size]));
+        keys.prune_to_max_size();
+        assert_eq!(keys.len(), 1);
+    }
+
+    #[test]
+    fn test_split_at_size() {
+        let keys = key_set::<OrderByInputs>(&[(1, 2), (2, 1), (2, 3), (3, 2)]);
+        let (small, large) = keys.clone().split_at_size(2, 2);
+        assert_eq!(small, key_set(&[(1, 2), (2, 1)]));
+        assert_eq!(large, key_set(&[(2, 3), (3, 2)]));
+
+        // The threshold itself goes in the large half.
+        let (small, large) = keys.clone().split_at_size(2, 3);
+        assert_eq!(small.len(), 2);
+        assert_eq!(large.min_size(), (2, 3));
+
+        let (small, large) = keys.clone().split_at_size(0, 0);
+        assert!(small.is_empty());
+        assert_eq!(large, keys);
+        let (small, large) = keys.clone().split_at_size(9, 9);
+        assert_eq!(small, keys);
+        assert!(large.is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_bincode_rejects_duplicate_keys() {
+        // A KeySet struct is encoded like a 1-tuple of its sequence of (sort key, key) pairs.
+        let pairs = vec![((1usize, 2usize), TestKey(1, 2)), ((3, 2), TestKey(3, 2))];
+        let bytes = bincode::serialize(&(pairs,)).unwrap();
+        assert_eq!(
+            bincode::deserialize::<KeySet<TestKey>>(&bytes).unwrap(),
+            key_set::<OrderByInputs>(&[(1, 2), (3, 2)])
+        );
+
+        let pairs = vec![((1usize, 2usize), TestKey(1, 2)), ((1, 2), TestKey(1, 2))];
+        let bytes = bincode::serialize(&(pairs,)).unwrap();
+        let err = bincode::deserialize::<KeySet<TestKey>>(&bytes)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("multiple keys with size (1 inputs, 2 outputs)"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_bincode_options() {
+        use bincode::Options;
+
+        let options = || bincode::DefaultOptions::new().with_big_endian();
+        let keys = key_set::<OrderByInputs>(&[(1, 2), (3, 2)]);
+        let bytes = keys.to_bincode(options()).unwrap();
+        assert_eq!(KeySet::from_bincode(&bytes, options()).unwrap(), keys);
+        assert_ne!(bytes, bincode::serialize(&keys).unwrap());
+
+        let (prover, verifier) = cap_key_sets();
+        let bytes = prover.to_bincode(options()).unwrap();
+        assert_eq!(
+            &ProverKeySet::from_bincode(&bytes, options()).unwrap(),
+            prover
+        );
+        let bytes = verifier.to_bincode(options()).unwrap();
+        assert_eq!(
+            &VerifierKeySet::from_bincode(&bytes, options()).unwrap(),
+            verifier
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_memory_footprint() {
+        let (prover, _) = cap_key_sets();
+        let mut smaller = prover.clone();
+        smaller.xfr.remove(2, 2).unwrap();
+        assert!(prover.memory_footprint() > smaller.memory_footprint());
+        assert_eq!(prover.memory_footprint(), prover.serialized_size());
+    }
+
+    #[test]
+    fn test_new_from_results() {
+        let keys = KeySet::<TestKey>::new_from_results(
+            vec![Ok::<_, &str>(TestKey(1, 2)), Ok(TestKey(3, 2))].into_iter(),
+        )
+        .unwrap();
+        assert_eq!(keys, key_set(&[(1, 2), (3, 2)]));
+
+        // A load error stops the build without consuming the rest of the keys.
+        let mut loaded = vec![Ok(TestKey(1, 2)), Err("unreadable"), Ok(TestKey(3, 2))].into_iter();
+        assert!(matches!(
+            KeySet::<TestKey>::new_from_results(loaded.by_ref()),
+            Err(KeySetOrLoadError::Load("unreadable"))
+        ));
+        assert_eq!(loaded.next(), Some(Ok(TestKey(3, 2))));
+
+        assert!(matches!(
+            KeySet::<TestKey>::new_from_results(core::iter::empty::<Result<TestKey, &str>>()),
+            Err(KeySetOrLoadError::KeySet(Error::NoKeys))
+        ));
+        assert!(matches!(
+            KeySet::<TestKey>::new_from_results(
+                vec![Ok::<_, &str>(TestKey(1, 2)), Ok(TestKey(1, 2))].into_iter()
+            ),
+            Err(KeySetOrLoadError::KeySet(Error::DuplicateKeys { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_find_duplicate_shapes() {
+        let keys = [
+            TestKey(3, 2),
+            TestKey(1, 2),
+            TestKey(3, 2),
+            TestKey(2, 2),
+            TestKey(1, 2),
+            TestKey(3, 2),
+        ];
+        assert_eq!(
+            find_duplicate_shapes::<_, OrderByInputs>(&keys),
+            vec![(1, 2), (3, 2)]
+        );
+        assert!(find_duplicate_shapes::<_, OrderByInputs>(&keys[..2]).is_empty());
+    }
+
+    #[test]
+    fn test_get_range() {
+        let keys = key_set::<OrderByInputs>(&[(1, 2), (2, 2), (3, 2), (4, 2)]);
+        let sizes = |low, high| {
+            keys.get_range(low, high)
+                .map(|(num_inputs, num_outputs, _)| (num_inputs, num_outputs))
+                .collect::<Vec<_>>()
+        };
+        // The low bound is inclusive and the high bound exclusive.
+        assert_eq!(sizes((2, 2), (4, 2)), vec![(2, 2), (3, 2)]);
+        assert_eq!(sizes((2, 1), (4, 3)), vec![(2, 2), (3, 2), (4, 2)]);
+        assert!(sizes((2, 2), (2, 2)).is_empty());
+        // An inverted range is empty rather than a panic.
+        assert!(sizes((4, 2), (1, 2)).is_empty());
+    }
+
+    #[test]
+    fn test_is_superset_of() {
+        let keys = key_set::<OrderByInputs>(&[(1, 2), (2, 2), (3, 2)]);
+        assert!(keys.is_superset_of(&key_set::<OrderByOutputs>(&[(1, 2), (3, 2)])));
+        assert!(keys.is_superset_of(&keys));
+        assert!(keys.is_superset_of(&KeySet::<TestKey>::default()));
+        assert!(!keys.is_superset_of(&key_set::<OrderByInputs>(&[(1, 2), (4, 2)])));
+        // A size covered only by a larger key does not count.
+        assert!(!keys.is_superset_of(&key_set::<OrderByInputs>(&[(1, 1)])));
+    }
+
+    #[test]
+    fn test_covers_size() {
+        let keys = key_set::<OrderByInputs>(&[(1, 4), (3, 2)]);
+        assert!(keys.covers_size(3, 2));
+        assert!(keys.covers_size(2, 1));
+        assert!(keys.covers_size(1, 3));
+        assert!(!keys.covers_size(2, 3));
+        assert!(!keys.covers_size(4, 1));
+        assert!(!KeySet::<TestKey>::default().covers_size(0, 0));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_iter_all_keys() {
+        let (prover, _) = cap_key_sets();
+        let keys = prover.iter_all_keys().collect::<Vec<_>>();
+        assert_eq!(keys.len(), 1 + prover.xfr.len() + prover.freeze.len());
+        assert!(matches!(keys[0], ProverKeyRef::Mint(_)));
+        assert!(keys[1..=prover.xfr.len()]
+            .iter()
+            .all(|key| matches!(key, ProverKeyRef::Transfer(_))));
+        assert!(keys[1 + prover.xfr.len()..]
+            .iter()
+            .all(|key| matches!(key, ProverKeyRef::Freeze(_))));
+    }
+
+    #[test]
+    fn test_histograms() {
+        let keys = key_set::<OrderByInputs>(&[(1, 2), (1, 3), (2, 2), (3, 2)]);
+        assert_eq!(
+            keys.input_histogram(),
+            vec![(1, 2), (2, 1), (3, 1)].into_iter().collect()
+        );
+        assert_eq!(
+            keys.output_histogram(),
+            vec![(2, 3), (3, 1)].into_iter().collect()
+        );
+        assert!(KeySet::<TestKey>::default().input_histogram().is_empty());
+        assert!(KeySet::<TestKey>::default().output_histogram().is_empty());
+    }
+
+    #[test]
+    fn test_suggest_next_shape() {
+        let keys = key_set::<OrderByOutputs>(&[(1, 2), (3, 1)]);
+        let next = keys.suggest_next_shape().unwrap();
+        assert_eq!(next, (2, 3));
+        assert!(!keys.contains_size(next.0, next.1));
+        assert_eq!(
+            OrderByOutputs::compare(next, keys.max_size()),
+            Ordering::Greater
+        );
+        assert_eq!(KeySet::<TestKey>::default().suggest_next_shape(), None);
+    }
+
+    #[test]
+    fn test_compare() {
+        fn check<Order: KeyOrder>() {
+            let sizes = [(1, 2), (2, 1), (2, 2), (3, 1), (1, 3)];
+            let keys = key_set::<Order>(&sizes);
+            let mut sorted = sizes.to_vec();
+            sorted.sort_by(|&a, &b| Order::compare(a, b));
+            assert_eq!(
+                keys.iter_sizes().map(|(size, _)| size).collect::<Vec<_>>(),
+                sorted
+            );
+        }
+        check::<OrderByInputs>();
+        check::<OrderByOutputs>();
+        assert_eq!(OrderByInputs::compare((1, 3), (2, 1)), Ordering::Less);
+        assert_eq!(OrderByOutputs::compare((1, 3), (2, 1)), Ordering::Greater);
+        assert_eq!(OrderByOutputs::compare((2, 2), (2, 2)), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_order_names() {
+        assert_eq!(OrderByInputs::name(), "by_inputs");
+        assert_eq!(OrderByOutputs::name(), "by_outputs");
+        assert_eq!(OrderBySum::name(), "by_sum");
+        assert_eq!(OrderByMax::name(), "by_max");
+    }
+
+    #[test]
+    fn test_best_fit_key_with_budget() {
+        let keys = key_set::<OrderByInputs>(&[(1, 4), (2, 1), (3, 1), (3, 3)]);
+        // The first key in range, (3, 1), is too small, so a budget of one key is not enough.
+        let err = keys.best_fit_key_with_budget(2, 3, 1).unwrap_err();
+        assert!(err.budget_exhausted);
+        assert_eq!(err.requested, (2, 3));
+        assert_eq!(
+            keys.best_fit_key_with_budget(2, 3, 2).unwrap(),
+            (3, 3, &TestKey(3, 3))
+        );
+        // When no key fits, the error is not blamed on the budget.
+        let err = keys.best_fit_key_with_budget(4, 1, 10).unwrap_err();
+        assert!(!err.budget_exhausted);
+        assert_eq!(err.largest_available, (3, 3));
+    }
+
+    #[test]
+    fn test_coverage_grid() {
+        let keys = key_set::<OrderByInputs>(&[(0, 1), (1, 2), (2, 1)]);
+        assert_eq!(
+            keys.coverage_grid(),
+            vec![
+                vec![false, true, false],
+                vec![false, false, true],
+                vec![false, true, false],
+            ]
+        );
+        assert!(KeySet::<TestKey>::default().coverage_grid().is_empty());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_strip_to_verifier_shapes() {
+        let (prover, verifier) = cap_key_sets();
+        let mut partial = verifier.clone();
+        partial.xfr.remove(1, 2).unwrap();
+
+        let mut stripped = prover.clone();
+        stripped.strip_to_verifier_shapes(&partial).unwrap();
+        stripped.verify_against(&partial).unwrap();
+        assert_eq!(stripped.xfr.len(), prover.xfr.len() - 1);
+        assert_eq!(stripped.freeze.len(), prover.freeze.len());
+
+        // Stripping every freeze key fails and leaves the prover unchanged.
+        partial.freeze.clear();
+        let mut unchanged = prover.clone();
+        assert!(matches!(
+            unchanged.strip_to_verifier_shapes(&partial),
+            Err(Error::NoKeys)
+        ));
+        assert_eq!(unchanged.shapes(), prover.shapes());
+    }
+
+    #[test]
+    fn test_first_and_last() {
+        fn check<Order: KeyOrder>() {
+            let keys = key_set::<Order>(&[(1, 3), (2, 2), (3, 1)]);
+            let (num_inputs, num_outputs, key) = keys.first().unwrap();
+            assert_eq!((num_inputs, num_outputs), keys.min_size());
+            assert_eq!(*key, TestKey(num_inputs, num_outputs));
+            let (num_inputs, num_outputs, key) = keys.last().unwrap();
+            assert_eq!((num_inputs, num_outputs), keys.max_size());
+            assert_eq!(*key, TestKey(num_inputs, num_outputs));
+        }
+        check::<OrderByInputs>();
+        check::<OrderByOutputs>();
+        assert!(KeySet::<TestKey>::default().first().is_none());
+        assert!(KeySet::<TestKey>::default().last().is_none());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_prover_key_set_builder() {
+        let (prover, _) = cap_key_sets();
+        let invalid = |builder: ProverKeySetBuilder<'static>| match builder.build() {
+            Err(Error::InvalidComponent { component, source }) => {
+                assert!(matches!(*source, Error::NoKeys));
+                component
+            }
+            res => panic!("expected InvalidComponent, got {:?}", res),
+        };
+
+        let mut builder = ProverKeySetBuilder::new();
+        builder
+            .transfer_keys(prover.xfr.iter().cloned())
+            .freeze_keys(prover.freeze.iter().cloned());
+        assert_eq!(invalid(builder.clone()), "mint");
+
+        builder.mint(prover.mint.clone());
+        let mut no_freeze = ProverKeySetBuilder::new();
+        no_freeze
+            .mint(prover.mint.clone())
+            .transfer_keys(prover.xfr.iter().cloned());
+        assert_eq!(invalid(no_freeze), "freeze");
+        let mut no_transfer = ProverKeySetBuilder::new();
+        no_transfer
+            .mint(prover.mint.clone())
+            .freeze_keys(prover.freeze.iter().cloned());
+        assert_eq!(invalid(no_transfer), "transfer");
+
+        assert_eq!(builder.build().unwrap().shapes(), prover.shapes());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_lazy_key_set_from_prover_file() {
+        let (prover, _) = cap_key_sets();
+        let path =
+            std::env::temp_dir().join(format!("key-set-lazy-prover-{}.bin", std::process::id()));
+        prover
+            .serialize_indexed(File::create(&path).unwrap())
+            .unwrap();
+
+        let xfr = LazyKeySet::<TransferProvingKey>::open_prover_transfer(&path).unwrap();
+        assert_eq!(
+            xfr.sizes().collect::<Vec<_>>(),
+            prover
+                .xfr
+                .iter_sizes()
+                .map(|(size, _)| size)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(xfr.exact_fit_key(1, 2).unwrap(), prover.xfr.get(1, 2));
+
+        let freeze = LazyKeySet::<FreezeProvingKey>::open_prover_freeze(&path).unwrap();
+        let (num_inputs, num_outputs) = prover.freeze.max_size();
+        assert_eq!(freeze.max_size(), Some((num_inputs, num_outputs)));
+        assert_eq!(
+            freeze.exact_fit_key(num_inputs, num_outputs).unwrap(),
+            prover.freeze.get(num_inputs, num_outputs)
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_compact() {
+        let sizes = (1..=20)
+            .map(|num_inputs| (num_inputs, 2))
+            .collect::<Vec<_>>();
+        let mut keys = key_set::<OrderByInputs>(&sizes);
+        for num_inputs in 1..=20 {
+            if num_inputs % 5 != 0 {
+                keys.remove(num_inputs, 2).unwrap();
+            }
+        }
+        let before = keys.clone();
+        keys.compact();
+        assert_eq!(keys, before);
+        assert_eq!(
+            keys.iter_sizes().map(|(size, _)| size).collect::<Vec<_>>(),
+            vec![(5, 2), (10, 2), (15, 2), (20, 2)]
+        );
+        assert_eq!(keys.best_fit_key(6, 1).unwrap(), (10, 2, &TestKey(10, 2)));
+    }
+
+    #[test]
+    fn test_best_fit_or_split() {
+        let keys = key_set::<OrderByInputs>(&[(1, 4), (2, 2), (4, 1)]);
+        match keys.best_fit_or_split(2, 1) {
+            BestFitPlan::Fit {
+                num_inputs,
+                num_outputs,
+                key,
+            } => {
+                assert_eq!((num_inputs, num_outputs), (2, 2));
+                assert_eq!(key, &TestKey(2, 2));
+            }
+            plan => panic!("expected a fit, got {:?}", plan),
+        }
+        match keys.best_fit_or_split(3, 3) {
+            BestFitPlan::Split {
+                most_inputs,
+                most_outputs,
+            } => {
+                assert_eq!(most_inputs, Some((4, 1, &TestKey(4, 1))));
+                assert_eq!(most_outputs, Some((1, 4, &TestKey(1, 4))));
+            }
+            plan => panic!("expected a split, got {:?}", plan),
+        }
+    }
+
+    #[test]
+    fn test_concise_debug() {
+        let keys: KeySet<TaggedKey> = vec![
+            TaggedKey(1, 2, vec![0xab; 1024]),
+            TaggedKey(2, 2, vec![0xab; 1024]),
+        ]
+        .into_iter()
+        .collect();
+        let debug = alloc::format!("{:?}", keys);
+        assert_eq!(
+            debug,
+            "KeySet { order: \"by_inputs\", shapes: [(1, 2), (2, 2)] }"
+        );
+        // The full representation includes the key data.
+        assert!(alloc::format!("{:?}", keys.debug_full()).len() > 2048);
+    }
+
+    #[test]
+    fn test_retain_largest_n() {
+        let sizes = [(1, 2), (2, 2), (3, 2), (4, 2)];
+        let retained = |n| {
+            let mut keys = key_set::<OrderByInputs>(&sizes);
+            keys.retain_largest_n(n);
+            keys.iter_sizes().map(|(size, _)| size).collect::<Vec<_>>()
+        };
+        assert_eq!(retained(2), vec![(3, 2), (4, 2)]);
+        assert_eq!(retained(4), sizes.to_vec());
+        assert_eq!(retained(10), sizes.to_vec());
+        assert!(retained(0).is_empty());
+
+        // Largest is by sort order, so the kept keys depend on the ordering.
+        let mut keys = key_set::<OrderByOutputs>(&[(1, 3), (2, 2), (3, 1)]);
+        keys.retain_largest_n(1);
+        assert_eq!(keys.max_size(), (1, 3));
+        assert_eq!(keys.len(), 1);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_compressed_and_uncompressed() {
+        let (prover, _) = cap_key_sets();
+        let keys = &prover.xfr;
+
+        let mut compressed = Vec::new();
+        keys.serialize_compressed_to(&mut compressed).unwrap();
+        assert_eq!(compressed.len(), keys.serialized_size());
+        let mut uncompressed = Vec::new();
+        keys.serialize_uncompressed_to(&mut uncompressed).unwrap();
+        assert_eq!(uncompressed.len(), keys.uncompressed_size());
+        assert!(uncompressed.len() > compressed.len());
+
+        assert_eq!(
+            &KeySet::<TransferProvingKey>::deserialize_compressed_from(&compressed[..]).unwrap(),
+            keys
+        );
+        assert_eq!(
+            &KeySet::<TransferProvingKey>::deserialize_uncompressed_from(&uncompressed[..])
+                .unwrap(),
+            keys
+        );
+    }
+
+    #[test]
+    fn test_shared_key_set_clone() {
+        let shared = key_set::<OrderByInputs>(&[(1, 2), (2, 2)]).into_shared();
+        let key = shared.get_shared(1, 2).unwrap();
+        assert_eq!(Arc::strong_count(&key), 2);
+
+        // Cloning the set shares the keys rather than copying them.
+        let clone = shared.clone();
+        assert_eq!(Arc::strong_count(&key), 3);
+        assert!(core::ptr::eq(
+            clone.get(1, 2).unwrap(),
+            shared.get(1, 2).unwrap()
+        ));
+        assert_eq!(clone.best_fit_key(2, 1).unwrap(), (2, 2, &TestKey(2, 2)));
+
+        drop(clone);
+        assert_eq!(Arc::strong_count(&key), 2);
+        drop(key);
+        assert_eq!(
+            shared.try_unwrap().unwrap(),
+            key_set::<OrderByInputs>(&[(1, 2), (2, 2)])
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_prover_and_verifier_reorder() {
+        fn sorted(mut sizes: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+            sizes.sort_unstable();
+            sizes
+        }
+
+        let (prover, verifier) = cap_key_sets();
+        let reordered_prover = prover.clone().reorder::<OrderByOutputs>();
+        let reordered_verifier = verifier.clone().reorder::<OrderByOutputs>();
+        for (shapes, reordered) in [
+            (prover.shapes(), reordered_prover.shapes()),
+            (verifier.shapes(), reordered_verifier.shapes()),
+        ] {
+            assert_eq!(reordered.mint, shapes.mint);
+            assert_eq!(sorted(reordered.xfr), sorted(shapes.xfr));
+            assert_eq!(sorted(reordered.freeze), sorted(shapes.freeze));
+        }
+        assert_eq!(reordered_prover.mint, prover.mint);
+        assert_eq!(reordered_verifier.mint, verifier.mint);
+
+        assert_eq!(
+            reordered_prover.xfr.best_fit_key(1, 1).unwrap().2,
+            prover.xfr.best_fit_key(1, 1).unwrap().2
+        );
+        assert_eq!(reordered_verifier.xfr.get(2, 2), verifier.xfr.get(2, 2));
+        reordered_prover.verify_against(verifier).unwrap();
+    }
+
+    #[test]
+    fn test_from_shaped_pairs() {
+        let keys = KeySet::<TestKey>::from_shaped_pairs(
+            vec![((1, 2), TestKey(1, 2)), ((3, 2), TestKey(3, 2))].into_iter(),
+        )
+        .unwrap();
+        assert_eq!(keys, key_set(&[(1, 2), (3, 2)]));
+
+        assert!(matches!(
+            KeySet::<TestKey>::from_shaped_pairs(
+                vec![((1, 2), TestKey(1, 2)), ((2, 3), TestKey(3, 2))].into_iter()
+            ),
+            Err(Error::ShapeMismatch {
+                declared: (2, 3),
+                actual: (3, 2)
+            })
+        ));
+        assert!(matches!(
+            KeySet::<TestKey>::from_shaped_pairs(
+                vec![((1, 2), TestKey(1, 2)), ((1, 2), TestKey(1, 2))].into_iter()
+            ),
+            Err(Error::DuplicateKeys { .. })
+        ));
+    }
+
+    #[test]
+    fn test_peek_best_fit_size() {
+        let keys = key_set::<OrderByInputs>(&[(1, 4), (2, 2), (3, 1), (3, 3)]);
+        for num_inputs in 0..5 {
+            for num_outputs in 0..5 {
+                let peeked = keys.peek_best_fit_size(num_inputs, num_outputs);
+                match keys.best_fit_key(num_inputs, num_outputs) {
+                    Ok((key_inputs, key_outputs, _)) => {
+                        assert_eq!(peeked, Ok((key_inputs, key_outputs)))
+                    }
+                    Err(err) => assert_eq!(peeked, Err(err)),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_iter_canonical() {
+        let sizes = [(3, 1), (1, 3), (2, 2), (1, 1)];
+        let by_inputs = key_set::<OrderByInputs>(&sizes);
+        let by_outputs = key_set::<OrderByOutputs>(&sizes);
+        assert_ne!(
+            by_inputs.iter().collect::<Vec<_>>(),
+            by_outputs.iter().collect::<Vec<_>>()
+        );
+        let canonical = by_inputs.iter_canonical().collect::<Vec<_>>();
+        assert_eq!(canonical, by_outputs.iter_canonical().collect::<Vec<_>>());
+        assert_eq!(
+            canonical
+                .iter()
+                .map(|&(num_inputs, num_outputs, _)| (num_inputs, num_outputs))
+                .collect::<Vec<_>>(),
+            vec![(1, 1), (1, 3), (2, 2), (3, 1)]
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_total_keys() {
+        let (prover, verifier) = cap_key_sets();
+        assert_eq!(prover.total_keys(), 4);
+        assert_eq!(verifier.total_keys(), 4);
+
+        let mut partial = prover.clone();
+        partial.xfr.remove(1, 2).unwrap();
+        assert_eq!(partial.total_keys(), 3);
+    }
+
+    #[test]
+    fn test_inner_round_trip() {
+        let keys = key_set::<OrderByOutputs>(&[(1, 2), (3, 1)]);
+        let inner = keys.clone().into_inner();
+        assert_eq!(inner.get(&(1, 3)), Some(&TestKey(3, 1)));
+        assert_eq!(KeySet::from_inner(inner).unwrap(), keys);
+
+        assert!(matches!(
+            KeySet::<TestKey, OrderByOutputs>::from_inner(BTreeMap::new()),
+            Err(Error::NoKeys)
+        ));
+        let mut corrupt = BTreeMap::new();
+        corrupt.insert((1, 2), TestKey(1, 2));
+        assert!(matches!(
+            KeySet::<TestKey, OrderByOutputs>::from_inner(corrupt),
+            Err(Error::CorruptIndex {
+                num_inputs: 1,
+                num_outputs: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn test_stats() {
+        let keys = key_set::<OrderByOutputs>(&[(1, 3), (2, 2), (4, 1)]);
+        let stats = keys.stats();
+        assert_eq!(stats.len, keys.len());
+        assert_eq!(stats.min_size, Some(keys.min_size()));
+        assert_eq!(stats.max_size, Some(keys.max_size()));
+        assert_eq!(stats.input_range, Some(keys.input_range()));
+        assert_eq!(stats.output_range, Some(keys.output_range()));
+        assert_eq!(stats.serialized_size, keys.serialized_size());
+        assert_eq!(stats.min_size, Some((4, 1)));
+        assert_eq!(stats.input_range, Some((1, 4)));
     }
 }